@@ -7,7 +7,8 @@ use parity_scale_codec::{Decode, Encode};
 use parking_lot::MappedMutexGuard;
 use sc_consensus::shared_data::{SharedData, SharedDataLocked};
 use sc_telemetry::{telemetry, TelemetryHandle, CONSENSUS_INFO};
-use sp_finality_pbft::{AuthorityId, AuthorityList};
+use sp_arithmetic::traits::Saturating;
+use sp_finality_pbft::{AuthorityId, AuthorityList, ViewNumber};
 
 use crate::SetId;
 
@@ -29,6 +30,10 @@ pub enum Error<N, E> {
 	ForcedAuthoritySetChangeDependencyUnsatisfied(N),
 	#[error("Invalid operation in the pending changes tree: {0}")]
 	ForkTree(fork_tree::Error<E>),
+	#[error("Invalid genesis authority set id {0}, the genesis set must have id 0.")]
+	InvalidGenesisSetId(SetId),
+	#[error("Invalid authorities JSON: {0}")]
+	InvalidAuthoritiesJson(String),
 }
 
 impl<N, E> From<fork_tree::Error<E>> for Error<N, E> {
@@ -142,6 +147,11 @@ pub struct AuthoritySet<H, N> {
 	/// for a given block since we can figure out what set the block belongs to and when the
 	/// set started/ended.
 	pub(crate) authority_set_changes: AuthoritySetChanges<N>,
+	/// The highest view seen for the current set, used to monitor liveness (a
+	/// high view means repeated view changes). Not persisted, it restarts from
+	/// scratch along with the node.
+	#[codec(skip)]
+	max_view_seen: Option<ViewNumber>,
 }
 
 impl<H, N> AuthoritySet<H, N>
@@ -166,6 +176,7 @@ where
 			pending_standard_changes: ForkTree::new(),
 			pending_forced_changes: Vec::new(),
 			authority_set_changes: AuthoritySetChanges::empty(),
+			max_view_seen: None,
 		})
 	}
 
@@ -187,6 +198,7 @@ where
 			pending_standard_changes,
 			pending_forced_changes,
 			authority_set_changes,
+			max_view_seen: None,
 		})
 	}
 
@@ -194,6 +206,17 @@ where
 	pub(crate) fn current(&self) -> (u64, &[AuthorityId]) {
 		(self.set_id, &self.current_authorities[..])
 	}
+
+	/// Record that the given view was seen for the current set.
+	pub fn record_view(&mut self, view: ViewNumber) {
+		self.max_view_seen = self.max_view_seen.max(Some(view));
+	}
+
+	/// The highest view seen for the current set, if any. It is reset when the
+	/// set changes.
+	pub fn max_view_seen(&self) -> Option<ViewNumber> {
+		self.max_view_seen
+	}
 }
 
 impl<H: Eq, N> AuthoritySet<H, N>
@@ -354,6 +377,34 @@ where
 			.chain(self.pending_forced_changes.iter())
 	}
 
+	/// Get the schedule of pending changes (both standard and forced, across
+	/// all forks) that would be enacted within the given inclusive range of
+	/// blocks. Returns the effective number of each change together with the
+	/// authorities it would switch to, ordered by effective number.
+	pub fn upcoming_changes(&self, from: N, to: N) -> Vec<(N, &AuthorityList)> {
+		let mut changes = self
+			.pending_changes()
+			.map(|change| (change.effective_number(), &change.next_authorities))
+			.filter(|(effective_number, _)| *effective_number >= from && *effective_number <= to)
+			.collect::<Vec<_>>();
+
+		changes.sort_by(|a, b| a.0.cmp(&b.0));
+		changes
+	}
+
+	/// Get the number of blocks left from `current` until the nearest pending change
+	/// (standard or forced, across all forks) is enacted, `Some(0)` if it can
+	/// already be applied, or `None` if there are no pending changes.
+	pub fn blocks_until_change(&self, current: N) -> Option<N>
+	where
+		N: Saturating,
+	{
+		self.pending_changes()
+			.map(|change| change.effective_number())
+			.min()
+			.map(|effective_number| effective_number.saturating_sub(current))
+	}
+
 	/// Get the earliest limit-block number, if any. If there are pending changes across
 	/// different forks, this method will return the earliest effective number (across the
 	/// different branches) that is higher or equal to the given min number.
@@ -456,6 +507,7 @@ where
 						pending_standard_changes: ForkTree::new(), // new set, new changes.
 						pending_forced_changes: Vec::new(),
 						authority_set_changes,
+						max_view_seen: None,
 					},
 				));
 
@@ -532,6 +584,7 @@ where
 
 					self.current_authorities = change.next_authorities;
 					self.set_id += 1;
+					self.max_view_seen = None;
 
 					status.new_set_block = Some((finalized_hash, finalized_number));
 				}
@@ -722,3 +775,296 @@ impl<N: Ord + Clone> AuthoritySetChanges<N> {
 		Some(self.0[idx..].iter())
 	}
 }
+
+/// Check that the given set id is the one of a genesis authority set. By
+/// convention the first set has id 0, starting from another id would desync the
+/// set id accounting with the runtime.
+pub(crate) fn validate_genesis_set_id<N, E>(set_id: SetId) -> Result<(), Error<N, E>> {
+	if set_id != 0 {
+		return Err(Error::InvalidGenesisSetId(set_id));
+	}
+
+	Ok(())
+}
+
+/// Render the given authorities in the JSON interchange format used by genesis
+/// tooling, i.e. an array of `{ "id": "<ss58>", "weight": <n> }` objects. PBFT
+/// authorities all vote with the same weight, hence the weight is always 1.
+pub fn export_authorities_json(authorities: &AuthorityList) -> serde_json::Value {
+	authorities
+		.iter()
+		.map(|id| serde_json::json!({ "id": id.to_string(), "weight": 1 }))
+		.collect()
+}
+
+/// Parse authorities from the JSON interchange format (see
+/// `export_authorities_json`). Each entry must have a valid SS58 encoded id and a
+/// weight of 1, and the resulting set must be non-empty without duplicates.
+/// Malformed entries are reported as `Error::InvalidAuthoritiesJson` and an
+/// empty set as `Error::InvalidAuthoritySet`.
+pub fn import_authorities_json<N, E>(
+	json: &serde_json::Value,
+) -> Result<AuthorityList, Error<N, E>> {
+	use sp_core::crypto::Ss58Codec;
+
+	let invalid = Error::<N, E>::InvalidAuthoritiesJson;
+
+	let entries = json
+		.as_array()
+		.ok_or_else(|| invalid("authorities must be a JSON array".into()))?;
+
+	let mut authorities = AuthorityList::with_capacity(entries.len());
+	for entry in entries {
+		let id = entry
+			.get("id")
+			.and_then(|id| id.as_str())
+			.ok_or_else(|| invalid(format!("authority entry without an id: {}", entry)))?;
+		let id = AuthorityId::from_ss58check(id)
+			.map_err(|e| invalid(format!("invalid authority id {}: {:?}", id, e)))?;
+
+		if entry.get("weight").and_then(|weight| weight.as_u64()) != Some(1) {
+			return Err(invalid(format!("authority {} must have a weight of 1", id)));
+		}
+
+		if authorities.contains(&id) {
+			return Err(invalid(format!("duplicate authority {}", id)));
+		}
+
+		authorities.push(id);
+	}
+
+	if authorities.is_empty() {
+		return Err(Error::InvalidAuthoritySet);
+	}
+
+	Ok(authorities)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn static_is_descendent_of<A>(value: bool) -> impl Fn(&A, &A) -> Result<bool, std::io::Error> {
+		move |_, _| Ok(value)
+	}
+
+	#[test]
+	fn genesis_set_id_must_be_zero() {
+		let genesis = vec![sp_finality_pbft::authority_id_from_bytes([1; 32])];
+		let genesis = AuthoritySet::<&str, u64>::genesis(genesis).unwrap();
+		assert!(validate_genesis_set_id::<u64, std::io::Error>(genesis.set_id).is_ok());
+
+		assert!(matches!(
+			validate_genesis_set_id::<u64, std::io::Error>(1),
+			Err(Error::InvalidGenesisSetId(1))
+		));
+	}
+
+	#[test]
+	fn changes_to_empty_authority_set_are_rejected() {
+		let genesis = vec![sp_finality_pbft::authority_id_from_bytes([1; 32])];
+		let mut authorities = AuthoritySet::<&str, u64>::genesis(genesis).unwrap();
+
+		let change = |delay_kind| PendingChange {
+			next_authorities: Vec::new(),
+			delay: 1,
+			canon_height: 5,
+			canon_hash: "hash_a",
+			delay_kind,
+		};
+
+		assert!(matches!(
+			authorities
+				.add_pending_change(change(DelayKind::Finalized), &static_is_descendent_of(false)),
+			Err(Error::InvalidAuthoritySet)
+		));
+
+		assert!(matches!(
+			authorities.add_pending_change(
+				change(DelayKind::Best { median_last_finalized: 0 }),
+				&static_is_descendent_of(false),
+			),
+			Err(Error::InvalidAuthoritySet)
+		));
+
+		assert_eq!(authorities.pending_changes().count(), 0);
+	}
+
+	#[test]
+	fn upcoming_changes_lists_changes_in_range() {
+		let authority = |byte| vec![sp_finality_pbft::authority_id_from_bytes([byte; 32])];
+		let mut authorities = AuthoritySet::<&str, u64>::genesis(authority(1)).unwrap();
+
+		let change = |next_authorities, canon_height, delay, canon_hash, delay_kind| {
+			PendingChange { next_authorities, delay, canon_height, canon_hash, delay_kind }
+		};
+
+		// two competing standard changes on different forks enacted at #15 and #12,
+		// and a forced change enacted at #20.
+		let is_descendent_of = static_is_descendent_of(false);
+		authorities
+			.add_pending_change(
+				change(authority(2), 10, 5, "hash_a", DelayKind::Finalized),
+				&is_descendent_of,
+			)
+			.unwrap();
+		authorities
+			.add_pending_change(
+				change(authority(3), 11, 1, "hash_b", DelayKind::Finalized),
+				&is_descendent_of,
+			)
+			.unwrap();
+		authorities
+			.add_pending_change(
+				change(authority(4), 15, 5, "hash_c", DelayKind::Best { median_last_finalized: 0 }),
+				&is_descendent_of,
+			)
+			.unwrap();
+
+		assert_eq!(
+			authorities.upcoming_changes(0, 100),
+			vec![(12, &authority(3)), (15, &authority(2)), (20, &authority(4))],
+		);
+
+		// the range is inclusive on both ends.
+		assert_eq!(
+			authorities.upcoming_changes(12, 15),
+			vec![(12, &authority(3)), (15, &authority(2))],
+		);
+		assert_eq!(authorities.upcoming_changes(16, 19), Vec::<(u64, &AuthorityList)>::new());
+	}
+
+	#[test]
+	fn authorities_round_trip_through_json() {
+		let authorities =
+			sp_finality_pbft::authorities_from_seeds(&["Alice", "Bob", "Charlie"]).unwrap();
+
+		let import =
+			|json: &serde_json::Value| import_authorities_json::<u64, std::io::Error>(json);
+
+		let json = export_authorities_json(&authorities);
+		assert_eq!(json[0]["weight"], 1);
+		assert_eq!(import(&json).unwrap(), authorities);
+
+		// duplicates, other weights, invalid ids and empty sets are rejected.
+		let mut duplicated = json.clone();
+		duplicated.as_array_mut().unwrap().push(json[0].clone());
+		assert!(matches!(import(&duplicated), Err(Error::InvalidAuthoritiesJson(_))));
+
+		let mut weighted = json.clone();
+		weighted[1]["weight"] = 2.into();
+		assert!(matches!(import(&weighted), Err(Error::InvalidAuthoritiesJson(_))));
+
+		let invalid_id = serde_json::json!([{ "id": "foo", "weight": 1 }]);
+		assert!(matches!(import(&invalid_id), Err(Error::InvalidAuthoritiesJson(_))));
+
+		assert!(matches!(import(&serde_json::json!([])), Err(Error::InvalidAuthoritySet)));
+	}
+
+	#[test]
+	fn forced_changes_count_imported_blocks_and_scheduled_changes_finalized_blocks() {
+		let authority = |byte| vec![sp_finality_pbft::authority_id_from_bytes([byte; 32])];
+
+		let change = |canon_hash, delay_kind| PendingChange {
+			next_authorities: authority(2),
+			delay: 5,
+			canon_height: 10,
+			canon_hash,
+			delay_kind,
+		};
+
+		let forced = change("hash_a", DelayKind::Best { median_last_finalized: 0 });
+		let scheduled = change("hash_b", DelayKind::Finalized);
+
+		// the forced change is applied on import of #15 without any finality.
+		let mut authorities = AuthoritySet::<&str, u64>::genesis(authority(1)).unwrap();
+		authorities.add_pending_change(forced, &static_is_descendent_of(false)).unwrap();
+		assert!(authorities
+			.apply_forced_changes("hash_c", 14, &static_is_descendent_of(true), false, None)
+			.unwrap()
+			.is_none());
+		let (median_last_finalized, new_set) = authorities
+			.apply_forced_changes("hash_c", 15, &static_is_descendent_of(true), false, None)
+			.unwrap()
+			.unwrap();
+		assert_eq!(median_last_finalized, 0);
+		assert_eq!(new_set.current_authorities, authority(2));
+
+		// the scheduled change is only applied once #15 is finalized.
+		let mut authorities = AuthoritySet::<&str, u64>::genesis(authority(1)).unwrap();
+		authorities
+			.add_pending_change(scheduled, &static_is_descendent_of(false))
+			.unwrap();
+		let status = authorities
+			.apply_standard_changes("hash_c", 14, &static_is_descendent_of(true), false, None)
+			.unwrap();
+		assert!(!status.changed);
+		let status = authorities
+			.apply_standard_changes("hash_d", 15, &static_is_descendent_of(true), false, None)
+			.unwrap();
+		assert!(status.changed);
+		assert_eq!(authorities.current_authorities, authority(2));
+	}
+
+	#[test]
+	fn blocks_until_change_counts_down_to_nearest_change() {
+		let authority = |byte| vec![sp_finality_pbft::authority_id_from_bytes([byte; 32])];
+		let mut authorities = AuthoritySet::<&str, u64>::genesis(authority(1)).unwrap();
+		assert_eq!(authorities.blocks_until_change(10), None);
+
+		let change = |canon_hash, canon_height, delay_kind| PendingChange {
+			next_authorities: authority(2),
+			delay: 5,
+			canon_height,
+			canon_hash,
+			delay_kind,
+		};
+
+		// a standard change enacted at #15 and a forced change enacted at #25.
+		let is_descendent_of = static_is_descendent_of(false);
+		authorities
+			.add_pending_change(change("hash_a", 10, DelayKind::Finalized), &is_descendent_of)
+			.unwrap();
+		authorities
+			.add_pending_change(
+				change("hash_b", 20, DelayKind::Best { median_last_finalized: 0 }),
+				&is_descendent_of,
+			)
+			.unwrap();
+
+		assert_eq!(authorities.blocks_until_change(12), Some(3));
+		assert_eq!(authorities.blocks_until_change(15), Some(0));
+		assert_eq!(authorities.blocks_until_change(18), Some(0));
+	}
+
+	#[test]
+	fn max_view_seen_is_tracked_per_set() {
+		let authority = |byte| vec![sp_finality_pbft::authority_id_from_bytes([byte; 32])];
+		let mut authorities = AuthoritySet::<&str, u64>::genesis(authority(1)).unwrap();
+		assert_eq!(authorities.max_view_seen(), None);
+
+		authorities.record_view(3);
+		authorities.record_view(7);
+		authorities.record_view(5);
+		assert_eq!(authorities.max_view_seen(), Some(7));
+
+		// not persisted.
+		let decoded = AuthoritySet::<&str, u64>::decode(&mut &authorities.encode()[..]);
+		assert_eq!(decoded.unwrap().max_view_seen(), None);
+
+		// the standard change at #5 is enacted, which resets the tracked view.
+		let change = PendingChange {
+			next_authorities: authority(2),
+			delay: 0,
+			canon_height: 5,
+			canon_hash: "hash_a",
+			delay_kind: DelayKind::Finalized,
+		};
+		authorities.add_pending_change(change, &static_is_descendent_of(false)).unwrap();
+		authorities
+			.apply_standard_changes("hash_a", 5, &static_is_descendent_of(false), false, None)
+			.unwrap();
+		assert_eq!(authorities.set_id, 1);
+		assert_eq!(authorities.max_view_seen(), None);
+	}
+}
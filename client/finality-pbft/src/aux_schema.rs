@@ -13,7 +13,7 @@ use sp_finality_pbft::{AuthorityList, SetId, ViewNumber};
 use sp_runtime::traits::{Block as BlockT, NumberFor};
 
 use crate::{
-	authorities::{AuthoritySet, SharedAuthoritySet},
+	authorities::{validate_genesis_set_id, AuthoritySet, SharedAuthoritySet},
 	environment::{SharedVoterSetState, VoterSetState},
 	NewAuthoritySet, justification::PbftJustification,
 };
@@ -112,6 +112,9 @@ where
 	let genesis_authorities = genesis_authorities()?;
 	let genesis_set = AuthoritySet::genesis(genesis_authorities)
 		.expect("genesis authorities is non-empty; all weights are non-zero; qed.");
+	validate_genesis_set_id::<NumberFor<Block>, ClientError>(genesis_set.set_id)
+		.map_err(|e| ClientError::Backend(e.to_string()))?;
+
 	let state = make_genesis_view();
 	let base = state
 		.finalized
@@ -261,6 +261,40 @@ impl<Block: BlockT> From<NeighborPacket<NumberFor<Block>>> for GossipMessage<Blo
 	}
 }
 
+/// Frame the given message (e.g. a gossip message) for a stream transport, i.e.
+/// prefix its SCALE encoding with its length as a little endian `u32`.
+pub fn frame_message<M: Encode>(msg: &M) -> Vec<u8> {
+	let encoded = msg.encode();
+	let mut frame = Vec::with_capacity(4 + encoded.len());
+	frame.extend_from_slice(&(encoded.len() as u32).to_le_bytes());
+	frame.extend_from_slice(&encoded);
+	frame
+}
+
+/// Consume one complete frame (see `frame_message`) from the start of the given
+/// buffer and decode the message in it. Returns `None`, leaving the buffer
+/// untouched, if the buffer doesn't hold a complete frame yet. A frame that fails
+/// to decode is still consumed, so that the stream can carry on with the next one.
+pub fn parse_frame<M: Decode>(buf: &mut Vec<u8>) -> Result<Option<M>, crate::Error> {
+	if buf.len() < 4 {
+		return Ok(None)
+	}
+
+	let len = u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]) as usize;
+	if len > sp_finality_pbft::MAX_MESSAGE_SIZE {
+		return Err(crate::Error::Network(format!("frame of {} bytes is too large", len)))
+	}
+
+	if buf.len() < 4 + len {
+		return Ok(None)
+	}
+
+	let frame = buf.drain(..4 + len).skip(4).collect::<Vec<_>>();
+	M::decode(&mut &frame[..])
+		.map(Some)
+		.map_err(|e| crate::Error::Network(format!("failed to decode framed message: {}", e)))
+}
+
 /// Network level message with topic information.
 #[derive(Debug, Encode, Decode)]
 pub(super) struct VoteMessage<Block: BlockT> {
@@ -870,6 +904,24 @@ impl<Block: BlockT> Inner<Block> {
 	) -> Action<Block::Hash> {
 		// always discard catch up messages, they're point-to-point
 		log::debug!(target: "afp", "validate_global_message: {:?}", full);
+
+		// view changes from any set other than the current one must never
+		// count towards a view change in the current set. the current set is
+		// only known once we have a local view, until then (e.g. right after
+		// startup) view changes are let through rather than stalling them.
+		if let (crate::GlobalMessage::ViewChange(_), Some(local_view)) =
+			(&full.message, self.local_view.as_ref())
+		{
+			if local_view.set_id != full.set_id {
+				debug!(target: "afp",
+					"Discarding view change from {} for set {:?}, not the current set",
+					who,
+					full.set_id,
+				);
+				return Action::Discard(cost::INVALID_VIEW_CHANGE)
+			}
+		}
+
 		let topic = super::global_topic::<Block>(full.set_id.0);
 		return Action::ProcessAndDiscard(topic, benefit::NEIGHBOR_MESSAGE)
 	}
@@ -1304,6 +1356,11 @@ impl<Block: BlockT> GossipValidator<Block> {
 		}
 	}
 
+	/// Get the height of the block finalized by the last commit we've observed, if any.
+	pub(super) fn last_commit_height(&self) -> Option<NumberFor<Block>> {
+		self.inner.read().local_view.as_ref().and_then(|v| v.last_commit_height().cloned())
+	}
+
 	/// Note that we've processed a catch up message.
 	pub(super) fn note_catch_up_message_processed(&self) {
 		self.inner.write().note_catch_up_message_processed();
@@ -1318,6 +1375,12 @@ impl<Block: BlockT> GossipValidator<Block> {
 		who: &PeerId,
 		mut data: &[u8],
 	) -> (Action<Block::Hash>, Vec<Block::Hash>, Option<GossipMessage<Block>>) {
+		// reject oversized messages before spending any effort decoding them.
+		if data.len() > sp_finality_pbft::MAX_MESSAGE_SIZE {
+			debug!(target: "afp", "Oversized message of {} bytes from {}", data.len(), who);
+			return (Action::Discard(cost::OVERSIZED_MESSAGE), Vec::new(), None)
+		}
+
 		let mut broadcast_topics = Vec::new();
 		let mut peer_reply = None;
 
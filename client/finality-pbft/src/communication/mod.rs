@@ -20,7 +20,7 @@ use sc_telemetry::{telemetry, TelemetryHandle, CONSENSUS_DEBUG, CONSENSUS_INFO};
 use sc_utils::mpsc::TracingUnboundedReceiver;
 use sp_finality_pbft::{AuthorityId, AuthoritySignature, SetId as SetIdNumber, ViewNumber};
 use sp_keystore::SyncCryptoStorePtr;
-use sp_runtime::traits::{Block as BlockT, Hash as HashT, Header as HeaderT, NumberFor};
+use sp_runtime::traits::{Block as BlockT, Hash as HashT, Header as HeaderT, NumberFor, One};
 
 use crate::{
 	communication::gossip::VoteMessage, environment::HasVoted, CatchUp, CompactCommit, Error,
@@ -30,6 +30,7 @@ use crate::{
 use self::gossip::{
 	FullCatchUpMessage, FullCommitMessage, GossipMessage, GossipValidator, PeerReport,
 };
+pub use self::gossip::{frame_message, parse_frame};
 
 mod gossip;
 
@@ -69,6 +70,7 @@ mod cost {
 	pub(super) const MALFORMED_COMMIT: Rep = Rep::new(-1000, "Grandpa: Malformed commit");
 	pub(super) const FUTURE_MESSAGE: Rep = Rep::new(-500, "Grandpa: Future message");
 	pub(super) const UNKNOWN_VOTER: Rep = Rep::new(-150, "Grandpa: Unknown voter");
+	pub(super) const OVERSIZED_MESSAGE: Rep = Rep::new(-1000, "Grandpa: Oversized message");
 
 	pub(super) const INVALID_VIEW_CHANGE: Rep = Rep::new(-500, "Grandpa: Invalid view change");
 	pub(super) const PER_UNDECODABLE_BYTE: i32 = -5;
@@ -564,8 +566,13 @@ fn incoming_global<B: BlockT>(
 
         log::debug!(target: "afp", "process_catch_up");
 
-		if let Err(cost) = check_catch_up::<B>(&msg.message, voters, msg.set_id, telemetry.clone())
-		{
+		if let Err(cost) = check_catch_up::<B>(
+			&msg.message,
+			voters,
+			msg.set_id,
+			gossip_validator.last_commit_height(),
+			telemetry.clone(),
+		) {
 			if let Some(who) = notification.sender {
 				gossip_engine.lock().report(who, cost);
 			}
@@ -631,6 +638,108 @@ pub struct View(pub ViewNumber);
 #[derive(Debug, Clone, Copy, Eq, PartialEq, PartialOrd, Ord, Encode, Decode)]
 pub struct SetId(pub SetIdNumber);
 
+/// Check that a message about to be signed by the local voter doesn't go back in
+/// time, i.e. that it's for the same or a later set than the last signed message
+/// and, within the same set, for the same or a later view. Signing for a lower
+/// view of the same set (e.g. after a restart with stale state) could look like an
+/// equivocation to others.
+pub fn check_local_monotonicity(
+	last: (SetIdNumber, ViewNumber),
+	next: (SetIdNumber, ViewNumber),
+) -> Result<(), Error> {
+	if next.0 < last.0 || (next.0 == last.0 && next.1 < last.1) {
+		return Err(Error::Safety(format!(
+			"refusing to sign for set {} view {} after signing for set {} view {}",
+			next.0, next.1, last.0, last.1,
+		)))
+	}
+
+	Ok(())
+}
+
+/// Check that a pre-prepare proposes the block right after the last finalized
+/// one. Blocks are finalized one at a time, a primary proposing a block number
+/// skipping ahead of or going back from that is misbehaving.
+pub fn check_proposal_sequence<N>(proposal_number: N, last_finalized: N) -> Result<(), Error>
+where
+	N: Copy + Ord + One + std::ops::Add<Output = N> + std::fmt::Debug,
+{
+	let expected = last_finalized + N::one();
+	if proposal_number != expected {
+		return Err(Error::Safety(format!(
+			"proposal for block #{:?} doesn't follow last finalized block #{:?}",
+			proposal_number, last_finalized,
+		)))
+	}
+
+	Ok(())
+}
+
+/// Check that the given header is the one of the block proposed by a
+/// pre-prepare, i.e. that we actually have the block we'd vote on. A replica
+/// should check this before preparing a proposal so it never votes on a hash it
+/// can't resolve to a block.
+pub fn proposal_header_link<Block: BlockT>(
+	proposal_hash: Block::Hash,
+	header: &Block::Header,
+) -> bool {
+	header.hash() == proposal_hash
+}
+
+/// A message received along with its signature, whose verification is deferred
+/// until the message is about to be acted on. The signature is checked at most
+/// once, the outcome is cached for later calls.
+pub struct LazyVerified<Block: BlockT> {
+	message: Message<Block>,
+	id: AuthorityId,
+	signature: AuthoritySignature,
+	view: ViewNumber,
+	set_id: SetIdNumber,
+	verified: std::cell::Cell<Option<bool>>,
+}
+
+impl<Block: BlockT> LazyVerified<Block> {
+	/// Wrap the given unverified message, signed for the given view and set.
+	pub fn new(
+		message: Message<Block>,
+		id: AuthorityId,
+		signature: AuthoritySignature,
+		view: ViewNumber,
+		set_id: SetIdNumber,
+	) -> Self {
+		LazyVerified { message, id, signature, view, set_id, verified: Default::default() }
+	}
+
+	/// Get the message if its signer is part of the given authorities and its
+	/// signature is valid.
+	pub fn verify(&self, authorities: &[AuthorityId]) -> Result<&Message<Block>, Error> {
+		if !authorities.contains(&self.id) {
+			return Err(Error::Network(format!("message from unknown voter {}", self.id)))
+		}
+
+		let valid = match self.verified.get() {
+			Some(valid) => valid,
+			None => {
+				let valid = sp_finality_pbft::check_message_signature(
+					&self.message,
+					&self.id,
+					&self.signature,
+					self.view,
+					self.set_id,
+				);
+				self.verified.set(Some(valid));
+				valid
+			},
+		};
+
+		if !valid {
+			return Err(Error::Network(format!("bad message signature from {}", self.id)))
+		}
+
+		Ok(&self.message)
+	}
+}
+
 /// A sink for outgoing messages to the network. Any messages that are sent will
 /// be replaced, as appropriate, according to the given `HasVoted`.
 /// NOTE: The votes are stored unsigned, which means that the signatures need to
@@ -813,11 +922,13 @@ fn check_compact_commit<Block: BlockT>(
 }
 
 // checks a catch up. returns the cost associated with processing it if
-// the catch up was bad.
+// the catch up was bad. if the currently finalized number is given the catch
+// up must finalize a higher block, otherwise it doesn't advance us.
 fn check_catch_up<Block: BlockT>(
 	msg: &CatchUp<Block>,
 	voters: &VoterSet<AuthorityId>,
 	set_id: SetId,
+	finalized_number: Option<NumberFor<Block>>,
 	telemetry: Option<TelemetryHandle>,
 ) -> Result<(), ReputationChange> {
 	let full_len = voters.len().get();
@@ -865,6 +976,14 @@ fn check_catch_up<Block: BlockT>(
 		full_len,
 	)?;
 
+	if let Some(finalized_number) = finalized_number {
+		let target_number = msg.commits.iter().map(|vote| vote.commit.target_number).max();
+		if target_number.map_or(true, |target_number| target_number <= finalized_number) {
+			debug!(target: "afp", "Skipping catch up message not advancing finalized block");
+			return Err(cost::HONEST_OUT_OF_SCOPE_CATCH_UP)
+		}
+	}
+
 	fn check_signatures<'a, B, I>(
 		messages: I,
 		view: ViewNumber,
@@ -1052,3 +1171,171 @@ impl<Block: BlockT> Sink<GlobalCommunicationOut<Block>> for GlobalMessagesOut<Bl
 		Poll::Ready(Ok(()))
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use sp_core::H256;
+	use sp_keyring::Ed25519Keyring;
+	use substrate_test_runtime_client::runtime::Block;
+
+	fn make_catch_up(signers: &[Ed25519Keyring], target: (H256, u64)) -> CatchUp<Block> {
+		let commits = signers
+			.iter()
+			.map(|keyring| {
+				let commit = leader::Commit { target_hash: target.0, target_number: target.1 };
+				let payload = sp_finality_pbft::localized_payload(
+					1,
+					0,
+					&leader::Message::Commit(commit.clone()),
+				);
+
+				leader::SignedCommit {
+					commit,
+					signature: keyring.sign(&payload[..]).into(),
+					id: keyring.public().into(),
+				}
+			})
+			.collect();
+
+		CatchUp::<Block> {
+			view_number: 1,
+			prepares: Vec::new(),
+			commits,
+			base_hash: target.0,
+			base_number: target.1,
+		}
+	}
+
+	#[test]
+	fn catch_up_must_advance_finalized_block() {
+		let signers = [Ed25519Keyring::Alice, Ed25519Keyring::Bob, Ed25519Keyring::Charlie];
+		let authorities: Vec<AuthorityId> =
+			signers.iter().map(|keyring| keyring.public().into()).collect();
+		let voters = VoterSet::new(authorities).unwrap();
+
+		let catch_up = make_catch_up(&signers, (H256::repeat_byte(1), 10));
+
+		// stateless verification doesn't care about the finalized block.
+		assert!(check_catch_up::<Block>(&catch_up, &voters, SetId(0), None, None).is_ok());
+		assert!(check_catch_up::<Block>(&catch_up, &voters, SetId(0), Some(9), None).is_ok());
+
+		assert_eq!(
+			check_catch_up::<Block>(&catch_up, &voters, SetId(0), Some(10), None),
+			Err(cost::HONEST_OUT_OF_SCOPE_CATCH_UP),
+		);
+		assert_eq!(
+			check_catch_up::<Block>(&catch_up, &voters, SetId(0), Some(11), None),
+			Err(cost::HONEST_OUT_OF_SCOPE_CATCH_UP),
+		);
+	}
+
+	#[test]
+	fn framed_messages_are_parsed_once_complete() {
+		let neighbor = |view| {
+			GossipMessage::<Block>::from(gossip::NeighborPacket {
+				view: View(view),
+				set_id: SetId(0),
+				commit_finalized_height: 1,
+			})
+		};
+
+		let first = gossip::frame_message(&neighbor(1));
+		let second = gossip::frame_message(&neighbor(2));
+
+		// nothing to parse until the first frame is complete.
+		let mut buf = first[..first.len() - 1].to_vec();
+		assert!(gossip::parse_frame::<GossipMessage<Block>>(&mut buf).unwrap().is_none());
+		assert_eq!(buf.len(), first.len() - 1);
+
+		buf.push(first[first.len() - 1]);
+		buf.extend_from_slice(&second[..2]);
+		match gossip::parse_frame::<GossipMessage<Block>>(&mut buf).unwrap() {
+			Some(GossipMessage::Neighbor(gossip::VersionedNeighborPacket::V1(packet))) =>
+				assert_eq!(packet.view, View(1)),
+			_ => panic!("expected a neighbor packet"),
+		}
+		assert_eq!(buf, second[..2].to_vec());
+
+		buf.extend_from_slice(&second[2..]);
+		assert!(gossip::parse_frame::<GossipMessage<Block>>(&mut buf).unwrap().is_some());
+		assert!(buf.is_empty());
+
+		// a malformed frame is consumed and reported.
+		let mut buf = vec![1, 0, 0, 0, 255];
+		assert!(gossip::parse_frame::<GossipMessage<Block>>(&mut buf).is_err());
+		assert!(buf.is_empty());
+	}
+
+	#[test]
+	fn local_messages_must_not_go_back_in_time() {
+		assert!(check_local_monotonicity((1, 5), (1, 5)).is_ok());
+		assert!(check_local_monotonicity((1, 5), (1, 6)).is_ok());
+
+		// a new set restarts from a lower view.
+		assert!(check_local_monotonicity((1, 5), (2, 0)).is_ok());
+
+		assert!(check_local_monotonicity((1, 5), (1, 4)).is_err());
+		assert!(check_local_monotonicity((2, 0), (1, 9)).is_err());
+	}
+
+	#[test]
+	fn proposals_must_follow_last_finalized_block() {
+		assert!(check_proposal_sequence(11u64, 10).is_ok());
+
+		// gaps and regressions are both rejected.
+		assert!(check_proposal_sequence(12u64, 10).is_err());
+		assert!(check_proposal_sequence(10u64, 10).is_err());
+		assert!(check_proposal_sequence(9u64, 10).is_err());
+	}
+
+	#[test]
+	fn proposal_must_link_to_the_fetched_header() {
+		let header = <Block as BlockT>::Header::new(
+			1,
+			Default::default(),
+			Default::default(),
+			H256::repeat_byte(1),
+			Default::default(),
+		);
+
+		assert!(proposal_header_link::<Block>(header.hash(), &header));
+		assert!(!proposal_header_link::<Block>(H256::repeat_byte(2), &header));
+		assert!(!proposal_header_link::<Block>(*header.parent_hash(), &header));
+	}
+
+	#[test]
+	fn lazy_verification_runs_once() {
+		let keyring = Ed25519Keyring::Alice;
+		let authorities = vec![keyring.public().into()];
+		let message = leader::Message::Prepare(leader::Prepare {
+			target_hash: H256::repeat_byte(1),
+			target_number: 1,
+		});
+		let payload = sp_finality_pbft::localized_payload(1, 0, &message);
+		let signature: AuthoritySignature = keyring.sign(&payload[..]).into();
+
+		let mut lazy = LazyVerified::<Block>::new(
+			message.clone(),
+			keyring.public().into(),
+			signature.clone(),
+			1,
+			0,
+		);
+		assert_eq!(lazy.verified.get(), None);
+		assert_eq!(lazy.verify(&authorities).unwrap(), &message);
+		assert_eq!(lazy.verified.get(), Some(true));
+
+		// the outcome is cached, the signature isn't checked again.
+		lazy.view = 2;
+		assert!(lazy.verify(&authorities).is_ok());
+
+		let bad = LazyVerified::<Block>::new(message, keyring.public().into(), signature, 2, 0);
+		assert!(bad.verify(&authorities).is_err());
+		assert_eq!(bad.verified.get(), Some(false));
+		assert!(bad.verify(&authorities).is_err());
+
+		// membership is checked on every call.
+		assert!(lazy.verify(&[]).is_err());
+	}
+}
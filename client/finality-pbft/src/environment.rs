@@ -2,7 +2,10 @@ use std::{
 	collections::{BTreeMap, HashMap},
 	marker::PhantomData,
 	pin::Pin,
-	sync::Arc,
+	sync::{
+		atomic::{AtomicU64, Ordering},
+		Arc,
+	},
 };
 
 use crate::{
@@ -54,6 +57,8 @@ pub(crate) struct Environment<Backend, Block: BlockT, C, N: NetworkT<Block>, SC>
 	pub(crate) network: crate::communication::NetworkBridge<Block, N>,
 	pub(crate) set_id: SetId,
 	pub(crate) voter_set_state: SharedVoterSetState<Block>,
+	/// The view in which a block was last finalized by this voter.
+	pub(crate) last_finalized_view: Arc<AtomicU64>,
 	pub(crate) metrics: Option<Metrics>,
 	pub(crate) justification_sender: Option<PbftJustificationSender<Block>>,
 	pub(crate) telemetry: Option<TelemetryHandle>,
@@ -176,6 +181,14 @@ where
 		if let Some(id) = local_id.as_ref() {
 			self.voter_set_state.started_voting_on(view, id.clone());
 		}
+
+		if let Some(metrics) = self.metrics.as_ref() {
+			let last_finalized_view = self.last_finalized_view.load(Ordering::Relaxed);
+			metrics
+				.finality_pbft_views_since_finality
+				.set(sp_finality_pbft::views_since_finality(view, last_finalized_view));
+		}
+
 		// we can only sign when we have a local key in the authority set
 		// and we have a reference to the keystore.
 		let keystore = match (local_id.as_ref(), self.config.keystore.as_ref()) {
@@ -294,7 +307,14 @@ where
 			false,
 			self.justification_sender.as_ref(),
 			self.telemetry.clone(),
-		)
+		)?;
+
+		self.last_finalized_view.store(view, Ordering::Relaxed);
+		if let Some(metrics) = self.metrics.as_ref() {
+			metrics.finality_pbft_views_since_finality.set(0);
+		}
+
+		Ok(())
 	}
 }
 
@@ -690,6 +710,7 @@ impl<Block: BlockT> SharedVoterSetState<Block> {
 #[derive(Clone)]
 pub(crate) struct Metrics {
 	finality_pbft_view: Gauge<U64>,
+	finality_pbft_views_since_finality: Gauge<U64>,
 	finality_pbft_prepares: Counter<U64>,
 	finality_pbft_commits: Counter<U64>,
 }
@@ -703,6 +724,13 @@ impl Metrics {
 				Gauge::new("substrate_finality_pbft_round", "Highest completed PBFT round.")?,
 				registry,
 			)?,
+			finality_pbft_views_since_finality: register(
+				Gauge::new(
+					"substrate_finality_pbft_views_since_finality",
+					"Number of PBFT views started since a block was last finalized.",
+				)?,
+				registry,
+			)?,
 			finality_pbft_prepares: register(
 				Counter::new(
 					"substrate_finality_pbft_prepares_total",
@@ -6,13 +6,25 @@ use std::{
 use finality_grandpa::leader::{self, Error as PbftError, VoterSet};
 use parity_scale_codec::{Decode, Encode};
 use sp_blockchain::{Error as ClientError, HeaderBackend};
-use sp_finality_pbft::AuthorityId;
+use sp_finality_pbft::{AuthorityId, AuthoritySignature, ScheduledChange, SetId};
 use sp_runtime::{
 	generic::BlockId,
-	traits::{Block as BlockT, Header as HeaderT, NumberFor},
+	traits::{Block as BlockT, Header as HeaderT, NumberFor, Zero},
 };
 
-use crate::{AuthorityList, Error, FinalizedCommit};
+use crate::{AuthorityList, Error, FinalizedCommit, SignedMessage, SignedPrepare};
+
+/// The error returned when verifying a justification that targets the genesis
+/// block, which is final by definition.
+#[derive(Debug, thiserror::Error)]
+#[error("invalid commit target (genesis) in pbft justification")]
+pub struct GenesisTarget;
+
+impl From<GenesisTarget> for ClientError {
+	fn from(err: GenesisTarget) -> Self {
+		ClientError::Application(Box::new(err))
+	}
+}
 
 /// A GRANDPA justification for block finality, it includes a commit message and
 /// an ancestry proof including all headers routing all commit target blocks
@@ -86,8 +98,30 @@ impl<Block: BlockT> PbftJustification<Block> {
 	where
 		NumberFor<Block>: leader::BlockNumberOps,
 	{
+		// genesis is final by definition, a justification for it can only be
+		// malformed or adversarial.
+		if self.f_commit.target_number.is_zero() {
+			return Err(GenesisTarget.into())
+		}
+
+		// cheap early-out for commits padded with signers, before checking any signature.
+		let signers = self.f_commit.commits.iter().map(|signed| &signed.id).collect::<HashSet<_>>();
+		if signers.len() > voters.len().get() {
+			return Err(ClientError::BadJustification(
+				"more commit signers than authorities in pbft justification".to_string(),
+			));
+		}
+
 		let mut buf = Vec::new();
 		for signed in self.f_commit.commits.iter() {
+			// a signer from outside the set can't count towards the quorum
+			// below, and padding the commit with one is malformed.
+			if !voters.contains(&signed.id) {
+				return Err(ClientError::BadJustification(
+					"commit signer not part of the authority set in pbft justification".to_string(),
+				));
+			}
+
 			if !sp_finality_pbft::check_message_signature_with_buffer(
 				&leader::Message::Commit(signed.commit.clone()),
 				&signed.id,
@@ -102,21 +136,1599 @@ impl<Block: BlockT> PbftJustification<Block> {
 				));
 			}
 
-			if self.f_commit.target_hash == signed.commit.target_hash {
-				continue;
+			if self.f_commit.target_hash != signed.commit.target_hash {
+				return Err(ClientError::BadJustification(
+					// FIXME:
+					"invalid commit ancestry proof in pbft justification".to_string(),
+				));
+			}
+
+			// a commit for the right hash at another number can only be stitched in.
+			if self.f_commit.target_number != signed.commit.target_number {
+				return Err(ClientError::BadJustification(
+					"commit target number doesn't match pbft justification target".to_string(),
+				));
 			}
+		}
 
+		// every commit is valid at this point, so the distinct signers are the
+		// ones counted towards the quorum.
+		if signers.len() < voters.threshold() {
 			return Err(ClientError::BadJustification(
-				// FIXME:
-				"invalid commit ancestry proof in pbft justification".to_string(),
+				"not enough signers for quorum in pbft justification".to_string(),
 			));
 		}
 
 		Ok(())
 	}
 
+	/// Validate the commit like `verify`, additionally checking that it was
+	/// produced in the given view. Signatures are always checked against the view
+	/// carried by the justification, so an expected view that disagrees with it is
+	/// rejected rather than used to reconstruct the signed payloads.
+	pub fn verify_at_view(
+		&self,
+		view: u64,
+		set_id: u64,
+		authorities: &AuthorityList,
+	) -> Result<(), ClientError>
+	where
+		NumberFor<Block>: leader::BlockNumberOps,
+	{
+		if self.view != view {
+			return Err(ClientError::BadJustification(format!(
+				"pbft justification for view {} doesn't match expected view {}",
+				self.view, view,
+			)));
+		}
+
+		self.verify(set_id, authorities)
+	}
+
+	/// Validate the commit like `verify`, additionally rejecting justifications
+	/// claiming a view past the given limit, if any. A view far beyond the ones
+	/// seen for the set is implausible, nodes can pass the highest view they've
+	/// seen (see `AuthoritySet::max_view_seen`) plus some margin.
+	pub fn verify_with_max_view(
+		&self,
+		set_id: u64,
+		authorities: &AuthorityList,
+		max_view: Option<u64>,
+	) -> Result<(), ClientError>
+	where
+		NumberFor<Block>: leader::BlockNumberOps,
+	{
+		if let Some(max_view) = max_view.filter(|max_view| self.view > *max_view) {
+			return Err(ClientError::BadJustification(format!(
+				"pbft justification for view {} is past the maximum view {}",
+				self.view, max_view,
+			)));
+		}
+
+		self.verify(set_id, authorities)
+	}
+
+	/// The view this justification was produced in.
+	pub fn view(&self) -> u64 {
+		self.view
+	}
+
+	/// Sort the commits by the index of their signer in the given authority set,
+	/// so that justifications assembled from the same commits encode identically.
+	/// Commits from signers outside the set are moved last, in their original
+	/// order. Verification accepts commits in any order.
+	pub fn canonicalize(&mut self, authorities: &AuthorityList) {
+		let index = |id: &AuthorityId| {
+			authorities
+				.iter()
+				.position(|authority| authority == id)
+				.unwrap_or(authorities.len())
+		};
+
+		self.f_commit.commits.sort_by_key(|signed| index(&signed.id));
+	}
+
 	/// The target block number and hash that this justifications proves finality for.
 	pub fn target(&self) -> (NumberFor<Block>, Block::Hash) {
 		(self.f_commit.target_number, self.f_commit.target_hash)
 	}
 }
+
+/// Create a justification for the given target with commits signed by all the
+/// given pairs. Meant to be used by tests feeding justifications to block import,
+/// the result verifies under the authority list made of the pairs' public keys.
+#[cfg(any(test, feature = "test-helpers"))]
+pub fn make_test_justification<Block: BlockT>(
+	target: (Block::Hash, NumberFor<Block>),
+	pairs: &[sp_finality_pbft::AuthorityPair],
+	set_id: SetId,
+	view: sp_finality_pbft::ViewNumber,
+) -> PbftJustification<Block> {
+	use sp_core::Pair;
+
+	let commit = leader::Commit { target_hash: target.0, target_number: target.1 };
+	let payload =
+		sp_finality_pbft::localized_payload(view, set_id, &leader::Message::Commit(commit.clone()));
+
+	let commits = pairs
+		.iter()
+		.map(|pair| leader::SignedCommit {
+			commit: commit.clone(),
+			signature: pair.sign(&payload[..]),
+			id: pair.public(),
+		})
+		.collect();
+
+	PbftJustification {
+		view,
+		f_commit: FinalizedCommit::<Block> {
+			target_hash: target.0,
+			target_number: target.1,
+			commits,
+		},
+	}
+}
+
+/// Verify the commits for the given target from an iterator of signers and their
+/// signatures, without collecting them, e.g. to import commits of very large sets
+/// on memory constrained nodes. Signers seen are tracked in a bitset over the
+/// authority set and the commit payload is only encoded once.
+///
+/// Verification stops as soon as a quorum of distinct signers is reached, so
+/// signers past that point are never looked at, unlike `PbftJustification::verify`
+/// which rejects a justification with any invalid commit.
+pub fn verify_commit_streaming<Block, I>(
+	signers: I,
+	target: (Block::Hash, NumberFor<Block>),
+	view: u64,
+	authorities: &AuthorityList,
+	set_id: SetId,
+) -> Result<(), ClientError>
+where
+	Block: BlockT,
+	I: Iterator<Item = (AuthorityId, AuthoritySignature)>,
+{
+	if target.1.is_zero() {
+		return Err(GenesisTarget.into())
+	}
+
+	let commit = leader::Commit { target_hash: target.0, target_number: target.1 };
+	let payload =
+		sp_finality_pbft::localized_payload(view, set_id, &leader::Message::Commit(commit));
+
+	let threshold = sp_finality_pbft::quorum_threshold(authorities.len());
+	let mut seen = vec![0u8; (authorities.len() + 7) / 8];
+	let mut count = 0;
+	for (id, signature) in signers {
+		let index = authorities.iter().position(|authority| *authority == id).ok_or_else(|| {
+			ClientError::BadJustification(
+				"commit signer not part of the authority set in pbft justification".to_string(),
+			)
+		})?;
+
+		if seen[index / 8] & (1 << (index % 8)) != 0 {
+			continue
+		}
+
+		if !sp_finality_pbft::check_signature_over_payload(&payload, &id, &signature) {
+			return Err(ClientError::BadJustification(
+				"invalid signature for commit in pbft justification".to_string(),
+			));
+		}
+
+		seen[index / 8] |= 1 << (index % 8);
+		count += 1;
+		if count >= threshold {
+			return Ok(())
+		}
+	}
+
+	Err(ClientError::BadJustification(
+		"not enough signers for quorum in pbft justification".to_string(),
+	))
+}
+
+/// Verify the given justification knowing only the Merkle root of the authority
+/// set (see `sp_finality_pbft::authorities_merkle_root`), e.g. in a light client.
+///
+/// The proof bundle must contain one membership proof per commit in the
+/// justification, in the same order, i.e. `membership_proofs[i]` proves that
+/// the signer of the `i`-th commit is part of the set. Each signer must be a
+/// member, sign the justification target under `set_id`, and at least `quorum`
+/// distinct signers are required. A quorum of zero is rejected, like a
+/// justification without any commit.
+pub fn verify_commit_with_root<Block: BlockT>(
+	justification: &PbftJustification<Block>,
+	membership_proofs: &[sp_finality_pbft::MerkleProof],
+	root: [u8; 32],
+	set_id: SetId,
+	quorum: usize,
+) -> Result<(), ClientError> {
+	if justification.f_commit.target_number.is_zero() {
+		return Err(GenesisTarget.into())
+	}
+
+	if quorum == 0 {
+		return Err(ClientError::BadJustification(
+			"quorum of zero requested to verify pbft justification".to_string(),
+		));
+	}
+
+	let commits = &justification.f_commit.commits;
+	if commits.is_empty() {
+		return Err(ClientError::BadJustification("no commits in pbft justification".to_string()));
+	}
+
+	if commits.len() != membership_proofs.len() {
+		return Err(ClientError::BadJustification(
+			"bundle doesn't have a membership proof for each commit in pbft justification"
+				.to_string(),
+		));
+	}
+
+	let mut buf = Vec::new();
+	let mut signers = HashSet::new();
+	for (signed, proof) in commits.iter().zip(membership_proofs) {
+		if !sp_finality_pbft::verify_authority_membership(&signed.id, proof, root) {
+			return Err(ClientError::BadJustification(
+				"invalid membership proof for commit signer in pbft justification".to_string(),
+			));
+		}
+
+		if justification.f_commit.target_hash != signed.commit.target_hash {
+			return Err(ClientError::BadJustification(
+				"invalid commit ancestry proof in pbft justification".to_string(),
+			));
+		}
+
+		if justification.f_commit.target_number != signed.commit.target_number {
+			return Err(ClientError::BadJustification(
+				"commit target number doesn't match pbft justification target".to_string(),
+			));
+		}
+
+		if !sp_finality_pbft::check_message_signature_with_buffer(
+			&leader::Message::Commit(signed.commit.clone()),
+			&signed.id,
+			&signed.signature,
+			justification.view,
+			set_id,
+			&mut buf,
+		) {
+			return Err(ClientError::BadJustification(
+				"invalid signature for commit in pbft justification".to_string(),
+			));
+		}
+
+		signers.insert(&signed.id);
+	}
+
+	if signers.len() < quorum {
+		return Err(ClientError::BadJustification(
+			"not enough signers for quorum in pbft justification".to_string(),
+		));
+	}
+
+	Ok(())
+}
+
+/// A quorum of prepares for a block in a given view, proving that the block was
+/// prepared before being committed, along with the pre-prepare of the primary
+/// of the view proposing it.
+#[derive(Clone, Encode, Decode, PartialEq, Eq, Debug)]
+pub struct PreparedProof<Block: BlockT> {
+	/// The view the prepares were cast in.
+	pub view: u64,
+	/// The signed pre-prepare proposing the block.
+	pub pre_prepare: SignedMessage<Block>,
+	/// The signed prepares.
+	pub prepares: Vec<SignedPrepare<Block>>,
+}
+
+/// Verify a complete finality proof, i.e. that the prepared proof and the
+/// justification both carry a quorum of valid votes from the given authorities,
+/// were cast in the same view and agree on the target block.
+///
+/// Votes don't carry their view, it is part of the payload they're signed over.
+/// The pre-prepare and prepares are checked against the view of the prepared
+/// proof and the commits against the view of the justification, so checking both
+/// views are equal ensures every vote of the bundle was cast in that one view.
+pub fn verify_full_finality<Block: BlockT>(
+	prepared: &PreparedProof<Block>,
+	justification: &PbftJustification<Block>,
+	authorities: &AuthorityList,
+	set_id: SetId,
+) -> Result<(), ClientError>
+where
+	NumberFor<Block>: leader::BlockNumberOps,
+{
+	if prepared.view != justification.view {
+		return Err(ClientError::BadJustification(format!(
+			"prepared proof for view {} doesn't match pbft justification for view {}",
+			prepared.view, justification.view,
+		)));
+	}
+
+	let voters = VoterSet::new(authorities.to_vec())
+		.ok_or(ClientError::Consensus(sp_consensus::Error::InvalidAuthoritiesSet))?;
+
+	justification.verify_with_voter_set(set_id, &voters)?;
+
+	if !sp_finality_pbft::is_valid_primary(
+		&prepared.pre_prepare,
+		prepared.view,
+		set_id,
+		authorities,
+	) {
+		return Err(ClientError::BadJustification(
+			"pre-prepare in prepared proof isn't from the primary of the view".to_string(),
+		));
+	}
+
+	match &prepared.pre_prepare.message {
+		leader::Message::PrePrepare(pre_prepare)
+			if (pre_prepare.target_hash, pre_prepare.target_number) ==
+				(justification.f_commit.target_hash, justification.f_commit.target_number) => {},
+		_ =>
+			return Err(ClientError::BadJustification(
+				"pre-prepare target doesn't match pbft justification target".to_string(),
+			)),
+	}
+
+	let mut buf = Vec::new();
+	let mut prepare_signers = HashSet::new();
+	for signed in prepared.prepares.iter() {
+		if !voters.contains(&signed.id) {
+			return Err(ClientError::BadJustification(
+				"prepare signer not part of the authority set in prepared proof".to_string(),
+			));
+		}
+
+		if (signed.prepare.target_hash, signed.prepare.target_number) !=
+			(justification.f_commit.target_hash, justification.f_commit.target_number)
+		{
+			return Err(ClientError::BadJustification(
+				"prepared proof target doesn't match pbft justification target".to_string(),
+			));
+		}
+
+		if !sp_finality_pbft::check_message_signature_with_buffer(
+			&leader::Message::Prepare(signed.prepare.clone()),
+			&signed.id,
+			&signed.signature,
+			prepared.view,
+			set_id,
+			&mut buf,
+		) {
+			return Err(ClientError::BadJustification(
+				"invalid signature for prepare in prepared proof".to_string(),
+			));
+		}
+
+		prepare_signers.insert(&signed.id);
+	}
+
+	if prepare_signers.len() < voters.threshold() {
+		return Err(ClientError::BadJustification(
+			"not enough signers for quorum in prepared proof".to_string(),
+		));
+	}
+
+	Ok(())
+}
+
+/// Verify a batch of justifications that are all expected to have been produced
+/// by the given authority set, e.g. when importing many finalized blocks of the
+/// same set. Each justification must finalize the block it is paired with and
+/// verify under `set_id`, thus a batch mixing justifications from different sets
+/// is rejected. Verification stops at the first failure.
+pub fn verify_justification_batch<Block: BlockT>(
+	justifications: &[(Block::Hash, NumberFor<Block>, PbftJustification<Block>)],
+	authorities: &AuthorityList,
+	set_id: SetId,
+) -> Result<(), ClientError>
+where
+	NumberFor<Block>: leader::BlockNumberOps,
+{
+	let voters = VoterSet::new(authorities.to_vec())
+		.ok_or(ClientError::Consensus(sp_consensus::Error::InvalidAuthoritiesSet))?;
+
+	for (hash, number, justification) in justifications {
+		if justification.target() != (*number, *hash) {
+			let msg = "invalid commit target in pbft justification".to_string();
+			return Err(ClientError::BadJustification(msg))
+		}
+
+		justification.verify_with_voter_set(set_id, &voters)?;
+	}
+
+	Ok(())
+}
+
+/// Check that the given sequence of justifications, each paired with the id of
+/// the set that produced it, is consistent when syncing across set changes:
+/// the justified blocks must be strictly increasing, and the set id must never
+/// go backwards nor skip a set. Only the ordering is checked, each justification
+/// still needs to be verified under its set, e.g. with `verify_justification_batch`.
+pub fn verify_set_id_progression<Block: BlockT>(
+	justifications: &[(SetId, PbftJustification<Block>)],
+) -> Result<(), ClientError> {
+	for window in justifications.windows(2) {
+		let ((prev_set_id, prev), (set_id, next)) = (&window[0], &window[1]);
+
+		if next.target().0 <= prev.target().0 {
+			let msg = format!(
+				"justification for #{} doesn't follow justification for #{}",
+				next.target().0,
+				prev.target().0,
+			);
+			return Err(ClientError::BadJustification(msg))
+		}
+
+		if set_id < prev_set_id || *set_id > prev_set_id + 1 {
+			let msg = format!(
+				"invalid set id progression from {} to {} at #{}",
+				prev_set_id,
+				set_id,
+				next.target().0,
+			);
+			return Err(ClientError::BadJustification(msg))
+		}
+	}
+
+	Ok(())
+}
+
+/// A proof of an authority set handoff, it includes the header of the block
+/// signaling the change and a justification for that block. Light clients that
+/// know the old set can use it to learn the new set without following the chain.
+#[derive(Clone, Encode, Decode, PartialEq, Eq, Debug)]
+pub struct ChangeProof<Block: BlockT> {
+	/// The block signaling the change, it carries a `ScheduledChange` digest.
+	pub header: Block::Header,
+	/// A justification proving the finality of the header above.
+	pub justification: PbftJustification<Block>,
+}
+
+/// Bundle the header of a block signaling an authority set change with the
+/// justification that finalized it.
+pub fn make_change_proof<Block: BlockT>(
+	change_block_header: Block::Header,
+	justification: PbftJustification<Block>,
+) -> ChangeProof<Block> {
+	ChangeProof { header: change_block_header, justification }
+}
+
+/// Verify the given change proof under the old authority set and return the
+/// new authority set. The justification must finalize the header in the proof,
+/// and the new set is taken from the first consensus digest in the header with
+/// the PBFT engine id that decodes to a standard `ScheduledChange` (forced
+/// changes don't lead to justifications and can't be proven this way).
+pub fn verify_change_proof<Block: BlockT>(
+	set_id: SetId,
+	old_set: &AuthorityList,
+	proof: &ChangeProof<Block>,
+) -> Result<AuthorityList, ClientError>
+where
+	NumberFor<Block>: leader::BlockNumberOps,
+{
+	verify_scheduled_change(set_id, old_set, proof).map(|change| change.next_authorities)
+}
+
+/// Verify the given change proof like `verify_change_proof`, returning the whole
+/// scheduled change (i.e. along with its delay).
+fn verify_scheduled_change<Block: BlockT>(
+	set_id: SetId,
+	old_set: &AuthorityList,
+	proof: &ChangeProof<Block>,
+) -> Result<ScheduledChange<NumberFor<Block>>, ClientError>
+where
+	NumberFor<Block>: leader::BlockNumberOps,
+{
+	if proof.justification.target() != (*proof.header.number(), proof.header.hash()) {
+		let msg = "invalid commit target in pbft justification".to_string();
+		return Err(ClientError::BadJustification(msg));
+	}
+
+	proof.justification.verify(set_id, old_set)?;
+
+	crate::import::find_scheduled_change::<Block>(&proof.header).ok_or_else(|| {
+		ClientError::BadJustification(
+			"justified header doesn't signal an authority set change".to_string(),
+		)
+	})
+}
+
+/// An update allowing a light client to step across an authority set change,
+/// i.e. a proof of the handoff and optionally a justification of a later block
+/// produced by the new set.
+#[derive(Clone, Encode, Decode, PartialEq, Eq, Debug)]
+pub struct LightClientUpdate<Block: BlockT> {
+	/// The proof of the handoff, justified by the old set.
+	pub change: ChangeProof<Block>,
+	/// A justification for a block after the change, produced by the new set.
+	pub finality: Option<PbftJustification<Block>>,
+}
+
+/// Verify the given light client update under the old authority set and return
+/// the new authority set and its id. The change proof must verify under the old
+/// set (see `verify_change_proof`), and the finality proof, if any, must target a
+/// block after the change block and verify under the new set. The new set only
+/// takes over once the change is enacted, i.e. `delay` blocks after the change
+/// block, so the finality proof must not target a block before that.
+pub fn verify_light_update<Block: BlockT>(
+	old_set: &AuthorityList,
+	old_set_id: SetId,
+	update: &LightClientUpdate<Block>,
+) -> Result<(AuthorityList, SetId), ClientError>
+where
+	NumberFor<Block>: leader::BlockNumberOps,
+{
+	let change = verify_scheduled_change(old_set_id, old_set, &update.change)?;
+	let new_set = change.next_authorities;
+	let new_set_id = old_set_id + 1;
+
+	if let Some(justification) = &update.finality {
+		let change_number = *update.change.header.number();
+		if justification.target().0 <= change_number {
+			return Err(ClientError::BadJustification(
+				"finality proof in light client update doesn't target a block after the change"
+					.to_string(),
+			));
+		}
+
+		// blocks before the change is enacted are still finalized by the old set.
+		if justification.target().0 < change_number + change.delay {
+			return Err(ClientError::BadJustification(
+				"finality proof in light client update targets a block before the change is enacted"
+					.to_string(),
+			));
+		}
+
+		justification.verify(new_set_id, &new_set)?;
+	}
+
+	Ok((new_set, new_set_id))
+}
+
+/// A checkpoint a light client bootstraps from, i.e. a finalized block along with
+/// the authority set that was active at it.
+///
+/// Nothing in the checkpoint is verified: it must come from a source the client
+/// already trusts (e.g. shipped with the client or obtained out of band), checking
+/// justifications against it is only as sound as that source.
+#[derive(Clone, Encode, Decode, PartialEq, Eq, Debug)]
+pub struct TrustedCheckpoint<Block: BlockT> {
+	/// The number and hash of the checkpoint block.
+	pub block: (NumberFor<Block>, Block::Hash),
+	/// The authority set active at the checkpoint block.
+	pub authorities: AuthorityList,
+	/// The id of the authority set above.
+	pub set_id: SetId,
+}
+
+/// Verify the given justification against the authority set bundled with a
+/// trusted checkpoint, this is the entry point for syncing from a weak
+/// subjectivity checkpoint. The justification must not target a block below
+/// the checkpoint.
+pub fn verify_justification_self_contained<Block: BlockT>(
+	checkpoint: TrustedCheckpoint<Block>,
+	justification: &PbftJustification<Block>,
+) -> Result<(), ClientError>
+where
+	NumberFor<Block>: leader::BlockNumberOps,
+{
+	if justification.target().0 < checkpoint.block.0 {
+		return Err(ClientError::BadJustification(
+			"pbft justification targets a block below the trusted checkpoint".to_string(),
+		));
+	}
+
+	justification.verify(checkpoint.set_id, &checkpoint.authorities)
+}
+
+/// Assemble a justification for the given target out of buffered signed messages,
+/// e.g. the votes received for a view. Only commits for the exact target from
+/// distinct members of the set with a valid signature for the given view and set
+/// id are collected, and a justification is only returned once they reach quorum.
+pub fn extract_justification<Block: BlockT>(
+	messages: &[SignedMessage<Block>],
+	target: (Block::Hash, NumberFor<Block>),
+	view: u64,
+	set_id: SetId,
+	authorities: &AuthorityList,
+) -> Option<PbftJustification<Block>> {
+	if authorities.is_empty() {
+		return None
+	}
+
+	let mut buf = Vec::new();
+	let mut signers = Vec::<&AuthorityId>::new();
+	let mut commits = Vec::new();
+	for signed in messages {
+		let commit = match &signed.message {
+			leader::Message::Commit(commit) => commit,
+			_ => continue,
+		};
+
+		if (commit.target_hash, commit.target_number) != target ||
+			!authorities.contains(&signed.id) ||
+			signers.contains(&&signed.id)
+		{
+			continue
+		}
+
+		if sp_finality_pbft::check_message_signature_with_buffer(
+			&signed.message,
+			&signed.id,
+			&signed.signature,
+			view,
+			set_id,
+			&mut buf,
+		) {
+			signers.push(&signed.id);
+			commits.push(leader::SignedCommit {
+				commit: commit.clone(),
+				signature: signed.signature.clone(),
+				id: signed.id.clone(),
+			});
+		}
+	}
+
+	if commits.len() < sp_finality_pbft::quorum_threshold(authorities.len()) {
+		return None
+	}
+
+	Some(PbftJustification {
+		view,
+		f_commit: FinalizedCommit::<Block> {
+			target_hash: target.0,
+			target_number: target.1,
+			commits,
+		},
+	})
+}
+
+/// Get the messages sent by the given authority among the given buffered
+/// messages tagged with their view, sorted by view.
+pub fn authority_vote_log<Block: BlockT>(
+	messages: &[(u64, SignedMessage<Block>)],
+	id: &AuthorityId,
+) -> Vec<(u64, crate::Message<Block>)> {
+	let mut log = messages
+		.iter()
+		.filter(|(_, signed)| signed.id == *id)
+		.map(|(view, signed)| (*view, signed.message.clone()))
+		.collect::<Vec<_>>();
+
+	log.sort_by_key(|(view, _)| *view);
+	log
+}
+
+/// A bitfield of the members of the given authority set that signed the given
+/// justification, bit `i % 8` of byte `i / 8` standing for `authorities[i]`.
+pub fn participation_bitmap<Block: BlockT>(
+	justification: &PbftJustification<Block>,
+	authorities: &AuthorityList,
+) -> Vec<u8> {
+	let mut bitmap = vec![0u8; (authorities.len() + 7) / 8];
+	for signed in justification.f_commit.commits.iter() {
+		if let Some(index) = authorities.iter().position(|id| *id == signed.id) {
+			bitmap[index / 8] |= 1 << (index % 8);
+		}
+	}
+
+	bitmap
+}
+
+/// Get a stable id of the given justification for the given set, made of its
+/// target only so that partial justifications for the same block share it.
+pub fn commit_certificate_id<Block: BlockT>(
+	justification: &PbftJustification<Block>,
+	set_id: SetId,
+) -> [u8; 32] {
+	let (number, hash) = justification.target();
+	(hash, number, set_id).using_encoded(sp_core::hashing::blake2_256)
+}
+
+/// Get the authorities that signed both given justifications, in the order they
+/// appear in `a`.
+pub fn signer_overlap<Block: BlockT>(
+	a: &PbftJustification<Block>,
+	b: &PbftJustification<Block>,
+) -> Vec<AuthorityId> {
+	let b_signers = b.f_commit.commits.iter().map(|signed| &signed.id).collect::<HashSet<_>>();
+
+	let mut overlap = Vec::new();
+	for signed in a.f_commit.commits.iter() {
+		if b_signers.contains(&signed.id) && !overlap.contains(&signed.id) {
+			overlap.push(signed.id.clone());
+		}
+	}
+
+	overlap
+}
+
+/// Check whether the two given justifications prove that conflicting blocks
+/// were finalized, i.e. they target the same block number but different hashes
+/// and both verify under the given authority set, each with its own quorum of
+/// signers. Finality is unique, so this can only happen if the safety of the
+/// protocol was violated (e.g. more than a third of the set misbehaved) and
+/// should be treated as a critical failure.
+pub fn justifications_conflict<Block: BlockT>(
+	a: &PbftJustification<Block>,
+	b: &PbftJustification<Block>,
+	set_id: SetId,
+	authorities: &AuthorityList,
+) -> bool
+where
+	NumberFor<Block>: leader::BlockNumberOps,
+{
+	let ((a_number, a_hash), (b_number, b_hash)) = (a.target(), b.target());
+	if a_number != b_number || a_hash == b_hash {
+		return false;
+	}
+
+	a.verify(set_id, authorities).is_ok() && b.verify(set_id, authorities).is_ok()
+}
+
+/// Get the distinct blocks at the given height proven final by the given
+/// justifications that verify under the given authority set.
+pub fn committed_blocks_at<Block: BlockT>(
+	justifications: &[PbftJustification<Block>],
+	height: NumberFor<Block>,
+	set_id: SetId,
+	authorities: &AuthorityList,
+) -> Vec<Block::Hash>
+where
+	NumberFor<Block>: leader::BlockNumberOps,
+{
+	let voters = match VoterSet::new(authorities.to_vec()) {
+		Some(voters) => voters,
+		None => return Vec::new(),
+	};
+
+	let mut hashes = justifications
+		.iter()
+		.filter(|justification| justification.target().0 == height)
+		.filter(|justification| justification.verify_with_voter_set(set_id, &voters).is_ok())
+		.map(|justification| justification.target().1)
+		.collect::<Vec<_>>();
+
+	hashes.sort();
+	hashes.dedup();
+	hashes
+}
+
+/// Produce a human-readable summary of the given justification, counting only
+/// the signers that are part of the given authority set.
+pub fn finality_report<Block: BlockT>(
+	justification: &PbftJustification<Block>,
+	authorities: &AuthorityList,
+) -> String {
+	let mut signers = Vec::new();
+	for signed in justification.f_commit.commits.iter() {
+		if authorities.contains(&signed.id) && !signers.contains(&&signed.id) {
+			signers.push(&signed.id);
+		}
+	}
+
+	let short_ids = signers
+		.iter()
+		.map(|id| {
+			let bytes = sp_finality_pbft::authority_id_bytes(id);
+			format!("{}", sp_core::hexdisplay::HexDisplay::from(&&bytes[..4]))
+		})
+		.collect::<Vec<_>>()
+		.join(", ");
+
+	let (number, hash) = justification.target();
+	format!(
+		"Block #{} ({:?}) finalized in view {} by {}/{} validators (quorum {}): [{}]",
+		number,
+		hash,
+		justification.view,
+		signers.len(),
+		authorities.len(),
+		sp_finality_pbft::quorum_threshold(authorities.len()),
+		short_ids,
+	)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use sp_core::H256;
+	use sp_keyring::Ed25519Keyring;
+	use substrate_test_runtime_client::runtime::Block;
+
+	fn authorities(keyrings: &[Ed25519Keyring]) -> AuthorityList {
+		keyrings.iter().map(|keyring| keyring.public().into()).collect()
+	}
+
+	fn default_signers() -> ([Ed25519Keyring; 3], AuthorityList) {
+		let signers = [Ed25519Keyring::Alice, Ed25519Keyring::Bob, Ed25519Keyring::Charlie];
+		let authorities = authorities(&signers);
+		(signers, authorities)
+	}
+
+	fn make_justification(
+		signers: &[Ed25519Keyring],
+		target: (H256, u64),
+		view: u64,
+		set_id: u64,
+	) -> PbftJustification<Block> {
+		let pairs = signers
+			.iter()
+			.map(|keyring| keyring.pair().into())
+			.collect::<Vec<sp_finality_pbft::AuthorityPair>>();
+		make_test_justification(target, &pairs, set_id, view)
+	}
+
+	fn make_prepared_proof(
+		primary: Ed25519Keyring,
+		signers: &[Ed25519Keyring],
+		target: (H256, u64),
+		view: u64,
+	) -> PreparedProof<Block> {
+		let message = leader::Message::PrePrepare(leader::PrePrepare {
+			target_hash: target.0,
+			target_number: target.1,
+		});
+		let payload = sp_finality_pbft::localized_payload(view, 0, &message);
+		let pre_prepare = leader::SignedMessage {
+			message,
+			signature: primary.sign(&payload[..]).into(),
+			id: primary.public().into(),
+		};
+
+		let prepares = signers
+			.iter()
+			.map(|keyring| {
+				let prepare = leader::Prepare { target_hash: target.0, target_number: target.1 };
+				let payload = sp_finality_pbft::localized_payload(
+					view,
+					0,
+					&leader::Message::Prepare(prepare.clone()),
+				);
+
+				leader::SignedPrepare {
+					prepare,
+					signature: keyring.sign(&payload[..]).into(),
+					id: keyring.public().into(),
+				}
+			})
+			.collect();
+
+		PreparedProof { view, pre_prepare, prepares }
+	}
+
+	#[test]
+	fn justification_for_genesis_is_rejected() {
+		let signers = [Ed25519Keyring::Alice, Ed25519Keyring::Bob, Ed25519Keyring::Charlie];
+		let authorities = authorities(&signers);
+
+		let justification = make_justification(&signers, (H256::repeat_byte(1), 0), 1, 0);
+		assert!(matches!(
+			justification.verify(0, &authorities),
+			Err(ClientError::Application(err)) if err.is::<GenesisTarget>()
+		));
+
+		let justification = make_justification(&signers, (H256::repeat_byte(1), 1), 1, 0);
+		assert!(justification.verify(0, &authorities).is_ok());
+	}
+
+	#[test]
+	fn justification_batch_must_share_set_id() {
+		let signers = [Ed25519Keyring::Alice, Ed25519Keyring::Bob, Ed25519Keyring::Charlie];
+		let authorities = authorities(&signers);
+
+		let batch = |set_ids: &[u64]| {
+			set_ids
+				.iter()
+				.enumerate()
+				.map(|(i, set_id)| {
+					let target = (H256::repeat_byte(i as u8), i as u64 + 1);
+					(target.0, target.1, make_justification(&signers, target, 1, *set_id))
+				})
+				.collect::<Vec<_>>()
+		};
+
+		assert!(verify_justification_batch(&batch(&[1, 1, 1]), &authorities, 1).is_ok());
+		assert!(verify_justification_batch(&batch(&[1, 2, 1]), &authorities, 1).is_err());
+
+		// the paired block must match the justification target.
+		let mut mismatched = batch(&[1, 1]);
+		mismatched[1].1 += 1;
+		assert!(verify_justification_batch(&mismatched, &authorities, 1).is_err());
+
+		// every justification in the batch needs a quorum of its own.
+		let target = (H256::repeat_byte(9), 9);
+		let mut below_quorum = batch(&[1, 1]);
+		below_quorum.push((target.0, target.1, make_justification(&signers[..1], target, 1, 1)));
+		assert!(verify_justification_batch(&below_quorum, &authorities, 1).is_err());
+	}
+
+	#[test]
+	fn finality_report_summarizes_signers() {
+		let (signers, mut authorities) = default_signers();
+		authorities.push(Ed25519Keyring::Dave.public().into());
+
+		let justification = make_justification(&signers, (H256::repeat_byte(1), 10), 2, 0);
+		let report = finality_report(&justification, &authorities);
+
+		assert!(report.starts_with("Block #10 (0x0101"));
+		assert!(report.contains("finalized in view 2 by 3/4 validators (quorum 3)"));
+
+		let alice = sp_finality_pbft::authority_id_bytes(&signers[0].public().into());
+		assert!(
+			report.contains(&format!("{}", sp_core::hexdisplay::HexDisplay::from(&&alice[..4])))
+		);
+	}
+
+	#[test]
+	fn justification_with_non_member_signer_is_rejected() {
+		let members = [Ed25519Keyring::Alice, Ed25519Keyring::Bob, Ed25519Keyring::Charlie];
+		let authorities = authorities(&members);
+
+		// an outsider signing in place of a member, so the signer count fits the set.
+		let signers = [members[0], members[1], Ed25519Keyring::Dave];
+		let justification = make_justification(&signers, (H256::repeat_byte(1), 1), 1, 0);
+
+		assert!(matches!(
+			justification.verify(0, &authorities),
+			Err(ClientError::BadJustification(msg)) if msg.contains("not part of the authority set")
+		));
+	}
+
+	#[test]
+	fn commit_below_threshold_is_rejected() {
+		use Ed25519Keyring::{Alice, Bob, Charlie, Dave};
+
+		let authorities = authorities(&[Alice, Bob, Charlie, Dave]);
+		let target = (H256::repeat_byte(1), 1);
+		assert!(make_justification(&[Alice, Bob, Charlie], target, 1, 0)
+			.verify(0, &authorities)
+			.is_ok());
+
+		// members only, but too few of them (a repeated signer counts once).
+		for signers in [&[][..], &[Alice][..], &[Alice, Bob][..], &[Alice, Bob, Alice][..]] {
+			assert!(matches!(
+				make_justification(signers, target, 1, 0).verify(0, &authorities),
+				Err(ClientError::BadJustification(msg)) if msg.contains("quorum")
+			));
+		}
+	}
+
+	#[test]
+	fn conflicting_justifications_are_detected() {
+		let signers = [Ed25519Keyring::Alice, Ed25519Keyring::Bob, Ed25519Keyring::Charlie];
+		let authorities = authorities(&signers);
+
+		let a = make_justification(&signers, (H256::repeat_byte(1), 10), 1, 0);
+		let b = make_justification(&signers, (H256::repeat_byte(2), 10), 2, 0);
+		assert!(justifications_conflict(&a, &b, 0, &authorities));
+
+		// justifications for the same block or for different heights don't conflict.
+		let c = make_justification(&signers, (H256::repeat_byte(1), 10), 2, 0);
+		let d = make_justification(&signers, (H256::repeat_byte(2), 11), 1, 0);
+		assert!(!justifications_conflict(&a, &c, 0, &authorities));
+		assert!(!justifications_conflict(&a, &d, 0, &authorities));
+
+		// a fork only counts as finalized if both justifications are valid.
+		let e = make_justification(&signers, (H256::repeat_byte(2), 10), 2, 1);
+		assert!(!justifications_conflict(&a, &e, 0, &authorities));
+
+		// and each was signed by a quorum of the set.
+		let f = make_justification(&signers[..1], (H256::repeat_byte(2), 10), 2, 0);
+		assert!(!justifications_conflict(&a, &f, 0, &authorities));
+		assert!(!justifications_conflict(&f, &a, 0, &authorities));
+	}
+
+	#[test]
+	fn change_proof_yields_next_authorities() {
+		use sp_finality_pbft::{ConsensusLog, ScheduledChange, PBFT_ENGINE_ID};
+		use sp_runtime::generic::{Digest, DigestItem};
+		use substrate_test_runtime_client::runtime::Header;
+
+		let signers = [Ed25519Keyring::Alice, Ed25519Keyring::Bob, Ed25519Keyring::Charlie];
+		let old_set = authorities(&signers);
+		let next_authorities = authorities(&[Ed25519Keyring::Dave, Ed25519Keyring::Eve]);
+
+		let header = |logs| {
+			Header::new(
+				10,
+				Default::default(),
+				Default::default(),
+				Default::default(),
+				Digest { logs },
+			)
+		};
+		let change = DigestItem::Consensus(
+			PBFT_ENGINE_ID,
+			ConsensusLog::ScheduledChange(ScheduledChange {
+				next_authorities: next_authorities.clone(),
+				delay: 0u64,
+			})
+			.encode(),
+		);
+
+		let header_with_change = header(vec![change]);
+		let justification = make_justification(&signers, (header_with_change.hash(), 10), 1, 0);
+		let proof = make_change_proof(header_with_change, justification);
+		assert_eq!(verify_change_proof(0, &old_set, &proof).unwrap(), next_authorities);
+
+		// the old set must have produced the justification.
+		assert!(verify_change_proof(1, &old_set, &proof).is_err());
+		assert!(verify_change_proof(0, &next_authorities, &proof).is_err());
+
+		// a header without a change signal can't prove a handoff.
+		let header_without_change = header(Vec::new());
+		let justification = make_justification(&signers, (header_without_change.hash(), 10), 1, 0);
+		let proof = make_change_proof(header_without_change, justification);
+		assert!(verify_change_proof(0, &old_set, &proof).is_err());
+	}
+
+	#[test]
+	fn set_id_progression_must_not_regress_or_skip() {
+		let signers = [Ed25519Keyring::Alice, Ed25519Keyring::Bob, Ed25519Keyring::Charlie];
+
+		let sequence = |set_ids: &[u64]| {
+			set_ids
+				.iter()
+				.enumerate()
+				.map(|(i, set_id)| {
+					let target = (H256::repeat_byte(i as u8), (i as u64 + 1) * 10);
+					(*set_id, make_justification(&signers, target, 1, *set_id))
+				})
+				.collect::<Vec<_>>()
+		};
+
+		assert!(verify_set_id_progression(&sequence(&[0, 0, 1, 2, 2])).is_ok());
+		assert!(verify_set_id_progression(&sequence(&[0, 1, 0])).is_err());
+		assert!(verify_set_id_progression(&sequence(&[0, 2])).is_err());
+
+		// the justified blocks must be increasing as well.
+		let mut reordered = sequence(&[0, 0]);
+		reordered.swap(0, 1);
+		assert!(verify_set_id_progression(&reordered).is_err());
+	}
+
+	#[test]
+	fn committed_blocks_at_height_include_conflicting_forks() {
+		let (signers, authorities) = default_signers();
+		let (a, b) = (H256::repeat_byte(1), H256::repeat_byte(2));
+
+		let justifications = vec![
+			make_justification(&signers, (a, 10), 1, 0),
+			make_justification(&signers, (a, 10), 2, 0),
+			make_justification(&signers, (b, 10), 3, 0),
+			make_justification(&signers, (H256::repeat_byte(3), 11), 1, 0),
+			// signed under another set, thus not valid.
+			make_justification(&signers, (H256::repeat_byte(4), 10), 1, 1),
+			// signed by a single authority, thus below quorum.
+			make_justification(&signers[..1], (H256::repeat_byte(5), 10), 1, 0),
+		];
+
+		assert_eq!(committed_blocks_at(&justifications, 10, 0, &authorities), vec![a, b]);
+		assert_eq!(committed_blocks_at(&justifications, 12, 0, &authorities), Vec::<H256>::new());
+	}
+
+	#[test]
+	fn commit_verifies_against_authorities_merkle_root() {
+		let signers = [Ed25519Keyring::Alice, Ed25519Keyring::Bob, Ed25519Keyring::Charlie];
+		let authorities = authorities(&signers);
+		let root = sp_finality_pbft::authorities_merkle_root(&authorities);
+		let proofs = (0..signers.len() as u32)
+			.map(|i| sp_finality_pbft::authority_merkle_proof(&authorities, i).unwrap())
+			.collect::<Vec<_>>();
+
+		let justification = make_justification(&signers, (H256::repeat_byte(1), 1), 1, 0);
+		assert!(verify_commit_with_root(&justification, &proofs, root, 0, 3).is_ok());
+
+		// not enough signers for the requested quorum.
+		assert!(verify_commit_with_root(&justification, &proofs, root, 0, 4).is_err());
+
+		// a quorum of zero, or no commits at all, never verifies.
+		assert!(verify_commit_with_root(&justification, &proofs, root, 0, 0).is_err());
+		let mut empty = justification.clone();
+		empty.f_commit.commits.clear();
+		assert!(verify_commit_with_root(&empty, &[], root, 0, 0).is_err());
+		assert!(verify_commit_with_root(&empty, &[], root, 0, 1).is_err());
+
+		// proofs must be given in the same order as the commits.
+		let mut swapped = proofs.clone();
+		swapped.swap(0, 1);
+		assert!(verify_commit_with_root(&justification, &swapped, root, 0, 3).is_err());
+
+		// a missing proof is rejected.
+		assert!(verify_commit_with_root(&justification, &proofs[..2], root, 0, 2).is_err());
+
+		// a signer outside of the committed set is rejected.
+		let justification = make_justification(
+			&[Ed25519Keyring::Alice, Ed25519Keyring::Bob, Ed25519Keyring::Dave],
+			(H256::repeat_byte(1), 1),
+			1,
+			0,
+		);
+		assert!(verify_commit_with_root(&justification, &proofs, root, 0, 3).is_err());
+
+		// a validly signed commit for the same hash but another number.
+		let mut justification = make_justification(&signers, (H256::repeat_byte(1), 1), 1, 0);
+		let stitched = make_justification(&signers[2..], (H256::repeat_byte(1), 2), 1, 0);
+		justification.f_commit.commits[2] = stitched.f_commit.commits[0].clone();
+		assert!(matches!(
+			verify_commit_with_root(&justification, &proofs, root, 0, 3),
+			Err(ClientError::BadJustification(msg)) if msg.contains("target number")
+		));
+	}
+
+	#[test]
+	fn full_finality_requires_matching_prepared_proof() {
+		let signers = [Ed25519Keyring::Alice, Ed25519Keyring::Bob, Ed25519Keyring::Charlie];
+		let authorities = authorities(&signers);
+		let target = (H256::repeat_byte(1), 1);
+
+		let prepared = |signers: &[Ed25519Keyring], target: (H256, u64), view| {
+			make_prepared_proof(Ed25519Keyring::Bob, signers, target, view)
+		};
+
+		let justification = make_justification(&signers, target, 1, 0);
+		let proof = prepared(&signers, target, 1);
+		assert!(verify_full_finality(&proof, &justification, &authorities, 0).is_ok());
+
+		// the prepared proof must be from the same view as the commit.
+		let proof = prepared(&signers, target, 2);
+		assert!(verify_full_finality(&proof, &justification, &authorities, 0).is_err());
+
+		// and for the same block.
+		let proof = prepared(&signers, (H256::repeat_byte(2), 1), 1);
+		assert!(verify_full_finality(&proof, &justification, &authorities, 0).is_err());
+
+		// both need a quorum.
+		let proof = prepared(&signers[..1], target, 1);
+		assert!(verify_full_finality(&proof, &justification, &authorities, 0).is_err());
+		let justification = make_justification(&signers[..1], target, 1, 0);
+		let proof = prepared(&signers, target, 1);
+		assert!(verify_full_finality(&proof, &justification, &authorities, 0).is_err());
+	}
+
+	#[test]
+	fn justification_with_more_signers_than_authorities_is_rejected() {
+		let signers = [Ed25519Keyring::Alice, Ed25519Keyring::Bob, Ed25519Keyring::Charlie];
+		let authorities = authorities(&signers);
+
+		let mut padded = signers.to_vec();
+		padded.push(Ed25519Keyring::Dave);
+		let justification = make_justification(&padded, (H256::repeat_byte(1), 1), 1, 0);
+
+		assert!(matches!(
+			justification.verify(0, &authorities),
+			Err(ClientError::BadJustification(msg)) if msg.contains("more commit signers")
+		));
+	}
+
+	#[test]
+	fn justification_is_verified_under_its_own_view() {
+		let signers = [Ed25519Keyring::Alice, Ed25519Keyring::Bob, Ed25519Keyring::Charlie];
+		let authorities = authorities(&signers);
+
+		let mut justification = make_justification(&signers, (H256::repeat_byte(1), 1), 1, 0);
+		assert!(justification.verify_at_view(1, 0, &authorities).is_ok());
+
+		// an expected view disagreeing with the justification is caught.
+		assert!(matches!(
+			justification.verify_at_view(2, 0, &authorities),
+			Err(ClientError::BadJustification(msg)) if msg.contains("expected view")
+		));
+
+		// the signatures authenticate the view carried by the justification.
+		justification.view = 2;
+		assert!(justification.verify_at_view(2, 0, &authorities).is_err());
+	}
+
+	#[test]
+	fn light_update_steps_across_set_change() {
+		use sp_finality_pbft::{ConsensusLog, ScheduledChange, PBFT_ENGINE_ID};
+		use sp_runtime::generic::{Digest, DigestItem};
+		use substrate_test_runtime_client::runtime::Header;
+
+		let old_signers = [Ed25519Keyring::Alice, Ed25519Keyring::Bob, Ed25519Keyring::Charlie];
+		let new_signers = [Ed25519Keyring::Dave, Ed25519Keyring::Eve, Ed25519Keyring::Ferdie];
+		let old_set = authorities(&old_signers);
+		let new_set = authorities(&new_signers);
+
+		let delayed_change_header = |next_authorities, delay: u64| {
+			let change = DigestItem::Consensus(
+				PBFT_ENGINE_ID,
+				ConsensusLog::ScheduledChange(ScheduledChange { next_authorities, delay }).encode(),
+			);
+
+			Header::new(
+				10,
+				Default::default(),
+				Default::default(),
+				Default::default(),
+				Digest { logs: vec![change] },
+			)
+		};
+		let change_header = |next_authorities| delayed_change_header(next_authorities, 0);
+
+		let update = |next_authorities, finality_signers: &[Ed25519Keyring], target| {
+			let header = change_header(next_authorities);
+			let justification = make_justification(&old_signers, (header.hash(), 10), 1, 0);
+
+			LightClientUpdate::<Block> {
+				change: make_change_proof(header, justification),
+				finality: Some(make_justification(finality_signers, target, 1, 1)),
+			}
+		};
+
+		let later = (H256::repeat_byte(1), 20);
+		let valid = update(new_set.clone(), &new_signers, later);
+		assert_eq!(verify_light_update(&old_set, 0, &valid).unwrap(), (new_set.clone(), 1));
+
+		// the change proof alone is enough to learn the new set.
+		let mut without_finality = valid.clone();
+		without_finality.finality = None;
+		assert_eq!(
+			verify_light_update(&old_set, 0, &without_finality).unwrap(),
+			(new_set.clone(), 1)
+		);
+
+		// a justified change to a set that isn't the one finalizing later blocks.
+		let tampered = update(old_set.clone(), &new_signers, later);
+		assert!(verify_light_update(&old_set, 0, &tampered).is_err());
+
+		// tampering with the justified header breaks the old set's justification.
+		let mut tampered = valid.clone();
+		tampered.change.header = change_header(authorities(&[Ed25519Keyring::Dave]));
+		assert!(verify_light_update(&old_set, 0, &tampered).is_err());
+
+		// the finality proof must be for a block after the change.
+		let stale = update(authorities(&new_signers), &new_signers, (H256::repeat_byte(1), 10));
+		assert!(verify_light_update(&old_set, 0, &stale).is_err());
+
+		// a single commit out of three authorities justifies neither the change nor
+		// the later block.
+		let header = change_header(new_set.clone());
+		let justification = make_justification(&old_signers[..1], (header.hash(), 10), 1, 0);
+		let mut below_quorum = valid.clone();
+		below_quorum.change = make_change_proof(header, justification);
+		assert!(verify_light_update(&old_set, 0, &below_quorum).is_err());
+		let below_quorum = update(new_set.clone(), &new_signers[..1], later);
+		assert!(verify_light_update(&old_set, 0, &below_quorum).is_err());
+
+		// a change delayed by 5 blocks is enacted at #15, the old set still
+		// finalizes the blocks before that.
+		let header = delayed_change_header(new_set.clone(), 5);
+		let justification = make_justification(&old_signers, (header.hash(), 10), 1, 0);
+		let delayed = |target| LightClientUpdate::<Block> {
+			change: make_change_proof(header.clone(), justification.clone()),
+			finality: Some(make_justification(&new_signers, target, 1, 1)),
+		};
+		assert!(verify_light_update(&old_set, 0, &delayed((H256::repeat_byte(1), 14))).is_err());
+		assert_eq!(
+			verify_light_update(&old_set, 0, &delayed((H256::repeat_byte(1), 15))).unwrap(),
+			(new_set, 1)
+		);
+	}
+
+	#[test]
+	fn participation_bitmap_reflects_signers() {
+		let keyrings = [
+			Ed25519Keyring::Alice,
+			Ed25519Keyring::Bob,
+			Ed25519Keyring::Charlie,
+			Ed25519Keyring::Dave,
+			Ed25519Keyring::Eve,
+			Ed25519Keyring::Ferdie,
+			Ed25519Keyring::One,
+			Ed25519Keyring::Two,
+		];
+		let mut authorities = authorities(&keyrings);
+		authorities.push(sp_finality_pbft::authority_id_from_bytes([9; 32]));
+
+		let signers = [keyrings[0], keyrings[2], keyrings[7], Ed25519Keyring::Alice];
+		let justification = make_justification(&signers, (H256::repeat_byte(1), 1), 1, 0);
+
+		// 9 authorities take two bytes, the last one didn't sign.
+		assert_eq!(participation_bitmap(&justification, &authorities), vec![0b1000_0101, 0]);
+	}
+
+	#[test]
+	fn justification_is_extracted_once_quorum_commits_are_buffered() {
+		let keyrings = [
+			Ed25519Keyring::Alice,
+			Ed25519Keyring::Bob,
+			Ed25519Keyring::Charlie,
+			Ed25519Keyring::Dave,
+		];
+		let authorities = authorities(&keyrings);
+		let target = (H256::repeat_byte(1), 1);
+
+		let message = |keyring: Ed25519Keyring, message: crate::Message<Block>| {
+			let payload = sp_finality_pbft::localized_payload(1, 0, &message);
+			leader::SignedMessage {
+				message,
+				signature: keyring.sign(&payload[..]).into(),
+				id: keyring.public().into(),
+			}
+		};
+		let commit = |keyring, target: (H256, u64)| {
+			message(
+				keyring,
+				leader::Message::Commit(leader::Commit {
+					target_hash: target.0,
+					target_number: target.1,
+				}),
+			)
+		};
+
+		let mut pool = vec![
+			commit(Ed25519Keyring::Alice, target),
+			commit(Ed25519Keyring::Alice, target),
+			commit(Ed25519Keyring::Bob, target),
+			commit(Ed25519Keyring::Charlie, (H256::repeat_byte(2), 1)),
+			message(
+				Ed25519Keyring::Dave,
+				leader::Message::Prepare(leader::Prepare {
+					target_hash: target.0,
+					target_number: target.1,
+				}),
+			),
+		];
+		assert!(extract_justification(&pool, target, 1, 0, &authorities).is_none());
+
+		pool.push(commit(Ed25519Keyring::Dave, target));
+		let justification = extract_justification(&pool, target, 1, 0, &authorities).unwrap();
+		assert_eq!(justification.f_commit.commits.len(), 3);
+		assert!(justification.verify(0, &authorities).is_ok());
+
+		// the signatures are for another view.
+		assert!(extract_justification(&pool, target, 2, 0, &authorities).is_none());
+
+		// an empty set can't finalize anything, even if no commit is needed to
+		// reach its quorum.
+		assert!(extract_justification::<Block>(&[], target, 1, 0, &Vec::new()).is_none());
+	}
+
+	#[test]
+	fn justification_is_verified_against_trusted_checkpoint() {
+		let signers = [Ed25519Keyring::Alice, Ed25519Keyring::Bob, Ed25519Keyring::Charlie];
+		let checkpoint = TrustedCheckpoint::<Block> {
+			block: (1, H256::repeat_byte(1)),
+			authorities: authorities(&signers),
+			set_id: 1,
+		};
+
+		let justification = make_justification(&signers, (H256::repeat_byte(2), 2), 0, 1);
+		assert!(verify_justification_self_contained(checkpoint.clone(), &justification).is_ok());
+
+		// a justification produced by a different set is rejected.
+		let justification = make_justification(&signers, (H256::repeat_byte(2), 2), 0, 2);
+		assert!(verify_justification_self_contained(checkpoint.clone(), &justification).is_err());
+
+		let others = [Ed25519Keyring::Dave, Ed25519Keyring::Eve, Ed25519Keyring::Ferdie];
+		let justification = make_justification(&others, (H256::repeat_byte(2), 2), 0, 1);
+		assert!(verify_justification_self_contained(checkpoint.clone(), &justification).is_err());
+
+		// the bundled set is trusted, but it still needs to reach quorum.
+		let justification = make_justification(&signers[..2], (H256::repeat_byte(2), 2), 0, 1);
+		assert!(verify_justification_self_contained(checkpoint.clone(), &justification).is_err());
+
+		// as is one for a block below the checkpoint.
+		let mut checkpoint = checkpoint;
+		checkpoint.block.0 = 3;
+		let justification = make_justification(&signers, (H256::repeat_byte(2), 2), 0, 1);
+		assert!(verify_justification_self_contained(checkpoint, &justification).is_err());
+	}
+
+	#[test]
+	fn signer_overlap_is_the_intersection_of_signers() {
+		use Ed25519Keyring::{Alice, Bob, Charlie, Dave};
+
+		let a = make_justification(&[Alice, Bob, Charlie], (H256::repeat_byte(1), 1), 0, 0);
+		let b = make_justification(&[Dave, Charlie, Bob], (H256::repeat_byte(2), 2), 0, 0);
+		assert_eq!(signer_overlap(&a, &b), authorities(&[Bob, Charlie]));
+		assert_eq!(signer_overlap(&b, &a), authorities(&[Charlie, Bob]));
+
+		let c = make_justification(&[Dave], (H256::repeat_byte(3), 3), 0, 0);
+		assert!(signer_overlap(&a, &c).is_empty());
+	}
+
+	#[test]
+	fn canonicalized_justifications_encode_identically() {
+		use Ed25519Keyring::{Alice, Bob, Charlie, Dave};
+
+		let authorities = authorities(&[Alice, Bob, Charlie, Dave]);
+		let target = (H256::repeat_byte(1), 1);
+
+		let mut a = make_justification(&[Charlie, Alice, Dave], target, 0, 0);
+		let mut b = make_justification(&[Dave, Charlie, Alice], target, 0, 0);
+		assert!(a.verify(0, &authorities).is_ok() && b.verify(0, &authorities).is_ok());
+		assert_ne!(a.encode(), b.encode());
+
+		a.canonicalize(&authorities);
+		b.canonicalize(&authorities);
+		assert_eq!(a.encode(), b.encode());
+		assert_eq!(a, make_justification(&[Alice, Charlie, Dave], target, 0, 0));
+		assert!(a.verify(0, &authorities).is_ok());
+	}
+
+	#[test]
+	fn justification_past_max_view_is_rejected() {
+		let signers = [Ed25519Keyring::Alice, Ed25519Keyring::Bob, Ed25519Keyring::Charlie];
+		let authorities = authorities(&signers);
+		let justification = make_justification(&signers, (H256::repeat_byte(1), 1), 100, 0);
+
+		assert!(justification.verify_with_max_view(0, &authorities, None).is_ok());
+		assert!(justification.verify_with_max_view(0, &authorities, Some(100)).is_ok());
+		assert!(matches!(
+			justification.verify_with_max_view(0, &authorities, Some(10)),
+			Err(ClientError::BadJustification(msg)) if msg.contains("maximum view")
+		));
+	}
+
+	#[test]
+	fn prepared_proof_must_be_anchored_by_the_primary() {
+		let signers = [Ed25519Keyring::Alice, Ed25519Keyring::Bob, Ed25519Keyring::Charlie];
+		let authorities = authorities(&signers);
+		let target = (H256::repeat_byte(1), 1);
+		let justification = make_justification(&signers, target, 2, 0);
+
+		// charlie is the primary of view 2.
+		let proof = make_prepared_proof(Ed25519Keyring::Charlie, &signers, target, 2);
+		assert!(verify_full_finality(&proof, &justification, &authorities, 0).is_ok());
+
+		// a pre-prepare from a backup is rejected, even with a quorum of prepares.
+		let proof = make_prepared_proof(Ed25519Keyring::Alice, &signers, target, 2);
+		assert!(matches!(
+			verify_full_finality(&proof, &justification, &authorities, 0),
+			Err(ClientError::BadJustification(msg)) if msg.contains("primary")
+		));
+
+		// as is a pre-prepare from the primary for another block.
+		let mut proof = make_prepared_proof(Ed25519Keyring::Charlie, &signers, target, 2);
+		proof.pre_prepare =
+			make_prepared_proof(Ed25519Keyring::Charlie, &[], (H256::repeat_byte(2), 1), 2)
+				.pre_prepare;
+		assert!(verify_full_finality(&proof, &justification, &authorities, 0).is_err());
+	}
+
+	#[test]
+	fn commit_for_another_number_is_rejected() {
+		let signers = [Ed25519Keyring::Alice, Ed25519Keyring::Bob, Ed25519Keyring::Charlie];
+		let authorities = authorities(&signers);
+		let target = (H256::repeat_byte(1), 1);
+		let mut justification = make_justification(&signers, target, 0, 0);
+		assert!(justification.verify(0, &authorities).is_ok());
+
+		// a validly signed commit for the same hash but another number.
+		let stitched = make_justification(&signers[2..], (target.0, 2), 0, 0);
+		justification.f_commit.commits[2] = stitched.f_commit.commits[0].clone();
+		assert!(matches!(
+			justification.verify(0, &authorities),
+			Err(ClientError::BadJustification(msg)) if msg.contains("target number")
+		));
+	}
+
+	#[test]
+	fn streaming_verification_matches_full_verification() {
+		use Ed25519Keyring::{Alice, Bob, Charlie, Dave};
+
+		let authorities = authorities(&[Alice, Bob, Charlie, Dave]);
+		let target = (H256::repeat_byte(1), 1);
+		let streaming = |justification: &PbftJustification<Block>| {
+			let signers = justification
+				.f_commit
+				.commits
+				.iter()
+				.map(|signed| (signed.id.clone(), signed.signature.clone()));
+			verify_commit_streaming::<Block, _>(signers, target, 0, &authorities, 0)
+		};
+
+		let valid = make_justification(&[Alice, Bob, Charlie], target, 0, 0);
+		assert!(valid.verify(0, &authorities).is_ok());
+		assert!(streaming(&valid).is_ok());
+
+		// signed for another view, or by a signer from outside the set.
+		let other_view = make_justification(&[Alice, Bob, Charlie], target, 1, 0);
+		assert!(other_view.verify(0, &authorities).is_err());
+		assert!(streaming(&other_view).is_err());
+
+		let outsider = make_justification(&[Alice, Ed25519Keyring::Eve, Bob], target, 0, 0);
+		assert!(outsider.verify(0, &authorities).is_err());
+		assert!(streaming(&outsider).is_err());
+
+		// repeated signers don't add up to a quorum.
+		let repeated = make_justification(&[Alice, Bob, Alice, Bob], target, 0, 0);
+		assert!(streaming(&repeated).is_err());
+	}
+
+	#[test]
+	fn partial_justifications_for_same_target_share_id() {
+		use Ed25519Keyring::{Alice, Bob, Charlie};
+
+		let target = (H256::repeat_byte(1), 1);
+		let a = make_justification(&[Alice, Bob], target, 0, 0);
+		let b = make_justification(&[Charlie], target, 0, 0);
+		assert_ne!(a, b);
+		assert_eq!(commit_certificate_id(&a, 0), commit_certificate_id(&b, 0));
+
+		let other = make_justification(&[Alice, Bob], (H256::repeat_byte(2), 1), 0, 0);
+		assert_ne!(commit_certificate_id(&a, 0), commit_certificate_id(&other, 0));
+		assert_ne!(commit_certificate_id(&a, 0), commit_certificate_id(&a, 1));
+	}
+
+	#[test]
+	fn vote_log_lists_authority_messages_by_view() {
+		let signed = |keyring: Ed25519Keyring, view, number| {
+			let message = leader::Message::Prepare(leader::Prepare {
+				target_hash: H256::repeat_byte(1),
+				target_number: number,
+			});
+			let payload = sp_finality_pbft::localized_payload(view, 0, &message);
+			let signed = leader::SignedMessage {
+				message,
+				signature: keyring.sign(&payload[..]).into(),
+				id: keyring.public().into(),
+			};
+
+			(view, signed)
+		};
+
+		let messages: Vec<(u64, SignedMessage<Block>)> = vec![
+			signed(Ed25519Keyring::Alice, 3, 1),
+			signed(Ed25519Keyring::Bob, 1, 1),
+			signed(Ed25519Keyring::Alice, 1, 2),
+			signed(Ed25519Keyring::Alice, 1, 3),
+			signed(Ed25519Keyring::Bob, 2, 1),
+		];
+
+		let alice = Ed25519Keyring::Alice.public().into();
+		let log = authority_vote_log(&messages, &alice);
+		assert_eq!(
+			log,
+			vec![
+				(1, messages[2].1.message.clone()),
+				(1, messages[3].1.message.clone()),
+				(3, messages[0].1.message.clone()),
+			],
+		);
+
+		let charlie = Ed25519Keyring::Charlie.public().into();
+		assert!(authority_vote_log(&messages, &charlie).is_empty());
+	}
+
+	#[test]
+	fn full_finality_votes_must_share_one_view() {
+		let signers = [Ed25519Keyring::Alice, Ed25519Keyring::Bob, Ed25519Keyring::Charlie];
+		let authorities = authorities(&signers);
+		let target = (H256::repeat_byte(1), 1);
+
+		// bob is the primary of view 1 and charlie of view 2.
+		let proof = make_prepared_proof(Ed25519Keyring::Bob, &signers, target, 1);
+		let justification = make_justification(&signers, target, 1, 0);
+		assert!(verify_full_finality(&proof, &justification, &authorities, 0).is_ok());
+
+		// commits a view ahead of the prepares.
+		let ahead = make_justification(&signers, target, 2, 0);
+		assert!(matches!(
+			verify_full_finality(&proof, &ahead, &authorities, 0),
+			Err(ClientError::BadJustification(msg)) if msg.contains("view")
+		));
+
+		// prepares cast a view ahead of the one claimed by the prepared proof.
+		let mut proof_ahead = make_prepared_proof(Ed25519Keyring::Bob, &signers, target, 1);
+		proof_ahead.prepares =
+			make_prepared_proof(Ed25519Keyring::Charlie, &signers, target, 2).prepares;
+		assert!(verify_full_finality(&proof_ahead, &justification, &authorities, 0).is_err());
+
+		// as is the pre-prepare.
+		let mut proof_ahead = proof;
+		proof_ahead.pre_prepare =
+			make_prepared_proof(Ed25519Keyring::Bob, &signers, target, 2).pre_prepare;
+		assert!(verify_full_finality(&proof_ahead, &justification, &authorities, 0).is_err());
+	}
+}
@@ -67,8 +67,25 @@ pub(crate) mod until_imported;
 // pub mod warp_proof;
 
 pub use communication::pbft_protocol_name::standard_name as protocol_standard_name;
+pub use communication::{
+	check_local_monotonicity, check_proposal_sequence, frame_message, parse_frame,
+	proposal_header_link, LazyVerified,
+};
 pub use import::PbftBlockImport;
-pub use notification::{PbftJustificationSender, PbftJustificationStream};
+#[cfg(feature = "test-helpers")]
+pub use justification::make_test_justification;
+pub use justification::{
+	authority_vote_log, commit_certificate_id, committed_blocks_at, extract_justification,
+	finality_report, justifications_conflict, make_change_proof, participation_bitmap,
+	signer_overlap, verify_change_proof, verify_commit_streaming, verify_commit_with_root,
+	verify_full_finality, verify_justification_batch, verify_justification_self_contained,
+	verify_light_update, verify_set_id_progression, ChangeProof, GenesisTarget, LightClientUpdate,
+	PbftJustification, PreparedProof, TrustedCheckpoint,
+};
+pub use notification::{
+	compact_finality_event, fetch_justification, CompactFinalityEvent, PbftJustificationSender,
+	PbftJustificationStream,
+};
 use until_imported::UntilGlobalMessageBlocksImported;
 
 /// A PBFT message for a substrate chain.
@@ -86,6 +103,13 @@ pub type SignedMessage<Block> = leader::SignedMessage<
 pub type PrePrepare<Block> = leader::PrePrepare<NumberFor<Block>, <Block as BlockT>::Hash>;
 /// A prepare message for this chain's block type.
 pub type Prepare<Block> = leader::Prepare<NumberFor<Block>, <Block as BlockT>::Hash>;
+/// A signed prepare message for this chain's block type.
+pub type SignedPrepare<Block> = leader::SignedPrepare<
+	NumberFor<Block>,
+	<Block as BlockT>::Hash,
+	AuthoritySignature,
+	AuthorityId,
+>;
 /// A commit message for this chain's block type.
 pub type Commit<Block> = leader::Commit<NumberFor<Block>, <Block as BlockT>::Hash>;
 
@@ -463,6 +487,8 @@ where
 		};
 
 		let voters = persistent_data.authority_set.current_authorities();
+		// nothing was finalized by this voter yet, count from where it resumes.
+		let last_finalized_view = persistent_data.set_state.read().last_completed_view().number;
 		let env = Arc::new(Environment {
 			client,
 			select_chain,
@@ -472,6 +498,7 @@ where
 			set_id: persistent_data.authority_set.set_id(),
 			authority_set: persistent_data.authority_set.clone(),
 			voter_set_state: persistent_data.set_state,
+			last_finalized_view: Arc::new(last_finalized_view.into()),
 			metrics: metrics.as_ref().map(|m| m.environment.clone()),
 			justification_sender: Some(justification_sender),
 			telemetry: telemetry.clone(),
@@ -621,6 +648,8 @@ where
 					voters,
 					set_id: new.set_id,
 					voter_set_state: self.env.voter_set_state.clone(),
+					// views of the new set start over.
+					last_finalized_view: Arc::new(0.into()),
 					client: self.env.client.clone(),
 					select_chain: self.env.select_chain.clone(),
 					config: self.env.config.clone(),
@@ -734,7 +763,7 @@ pub fn pbft_peers_set_config(
 		notifications_protocol: protocol_name,
 		fallback_names: Vec::new(),
 		// Notifications reach ~256kiB in size at the time of writing on Kusama and Polkadot.
-		max_notification_size: 1024 * 1024,
+		max_notification_size: sp_finality_pbft::MAX_MESSAGE_SIZE as u64,
 		set_config: sc_network::config::SetConfig {
 			in_peers: 0,
 			out_peers: 0,
@@ -1106,3 +1135,59 @@ fn local_authority_id(
 			.map(|p| p.clone())
 	})
 }
+
+/// Check that the key of the given authority is stored in the keystore with the
+/// crypto scheme PBFT authorities sign with (i.e. ed25519). `has_keys` only looks
+/// at the raw public key and key type, so a key stored under another scheme (e.g.
+/// sr25519) would be found but could only produce signatures that are rejected
+/// by everyone else. This should be checked before starting to vote.
+pub fn verify_keystore_scheme(
+	keystore: &SyncCryptoStorePtr,
+	id: &AuthorityId,
+) -> Result<(), Error> {
+	let raw = id.to_raw_vec();
+
+	let ed25519_keys = SyncCryptoStore::ed25519_public_keys(&**keystore, AuthorityId::ID);
+	if ed25519_keys.iter().any(|key| key.0[..] == raw[..]) {
+		return Ok(())
+	}
+
+	let sr25519_keys = SyncCryptoStore::sr25519_public_keys(&**keystore, AuthorityId::ID);
+	if sr25519_keys.iter().any(|key| key.0[..] == raw[..]) {
+		return Err(Error::Signing(format!(
+			"key for authority {} is stored as sr25519 but pbft authorities use ed25519",
+			id,
+		)))
+	}
+
+	Err(Error::Signing(format!("no key for authority {} in the keystore", id)))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use sc_keystore::LocalKeystore;
+
+	#[test]
+	fn keystore_key_with_wrong_scheme_is_detected() {
+		let keystore: SyncCryptoStorePtr = Arc::new(LocalKeystore::in_memory());
+
+		let ed25519 =
+			SyncCryptoStore::ed25519_generate_new(&*keystore, AuthorityId::ID, Some("//Alice"))
+				.unwrap();
+		assert!(verify_keystore_scheme(&keystore, &ed25519.into()).is_ok());
+
+		// an sr25519 key with the pbft key type, read back as an authority id.
+		let sr25519 =
+			SyncCryptoStore::sr25519_generate_new(&*keystore, AuthorityId::ID, Some("//Bob"))
+				.unwrap();
+		let id = sp_finality_pbft::authority_id_from_bytes(sr25519.0);
+		assert!(matches!(
+			verify_keystore_scheme(&keystore, &id),
+			Err(Error::Signing(msg)) if msg.contains("sr25519")
+		));
+
+		let missing = sp_finality_pbft::authority_id_from_bytes([7; 32]);
+		assert!(verify_keystore_scheme(&keystore, &missing).is_err());
+	}
+}
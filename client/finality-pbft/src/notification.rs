@@ -1,4 +1,13 @@
+use std::collections::HashSet;
+
+use parity_scale_codec::{Decode, Encode};
 use sc_utils::notification::{NotificationSender, NotificationStream, TracingKeyStr};
+use sp_blockchain::{Backend as BlockchainBackend, Error as ClientError};
+use sp_finality_pbft::{SetId, PBFT_ENGINE_ID};
+use sp_runtime::{
+	generic::BlockId,
+	traits::{Block as BlockT, NumberFor},
+};
 
 use crate::justification::PbftJustification;
 
@@ -23,3 +32,83 @@ pub struct PbftJustificationsTracingKey;
 impl TracingKeyStr for PbftJustificationsTracingKey {
 	const TRACING_KEY: &'static str = "mpsc_pbft_justification_notification_stream";
 }
+
+/// A small finality event for subscribers only interested in which blocks are
+/// final, as opposed to the full justification proving it. The justification can
+/// be fetched separately with `fetch_justification`.
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+pub struct CompactFinalityEvent<Block: BlockT> {
+	/// The hash of the finalized block.
+	pub hash: Block::Hash,
+	/// The number of the finalized block.
+	pub number: NumberFor<Block>,
+	/// The view the block was finalized in.
+	pub view: u64,
+	/// The id of the authority set that finalized the block.
+	pub set_id: SetId,
+	/// The number of distinct authorities that signed the commit.
+	pub signer_count: u32,
+}
+
+/// Derive the compact finality event of a justification notification. The set
+/// id isn't part of the justification and must be given by the caller.
+pub fn compact_finality_event<Block: BlockT>(
+	justification: &PbftJustification<Block>,
+	set_id: SetId,
+) -> CompactFinalityEvent<Block> {
+	let (number, hash) = justification.target();
+	let signers = justification.f_commit.commits.iter().map(|signed| &signed.id);
+
+	CompactFinalityEvent {
+		hash,
+		number,
+		view: justification.view(),
+		set_id,
+		signer_count: signers.collect::<HashSet<_>>().len() as u32,
+	}
+}
+
+/// Fetch the full justification stored for the given finalized block, if any.
+pub fn fetch_justification<Block, B>(
+	blockchain: &B,
+	hash: Block::Hash,
+) -> Result<Option<PbftJustification<Block>>, ClientError>
+where
+	Block: BlockT,
+	B: BlockchainBackend<Block>,
+{
+	blockchain
+		.justifications(BlockId::Hash(hash))?
+		.and_then(|justifications| justifications.into_justification(PBFT_ENGINE_ID))
+		.map(|encoded| {
+			PbftJustification::decode(&mut &encoded[..])
+				.map_err(|err| ClientError::BadJustification(err.to_string()))
+		})
+		.transpose()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use sp_core::H256;
+	use sp_keyring::Ed25519Keyring;
+	use substrate_test_runtime_client::runtime::Block;
+
+	#[test]
+	fn compact_event_is_derived_from_justification() {
+		let pairs = [Ed25519Keyring::Alice, Ed25519Keyring::Bob, Ed25519Keyring::Charlie]
+			.iter()
+			.map(|keyring| keyring.pair().into())
+			.collect::<Vec<sp_finality_pbft::AuthorityPair>>();
+		let target = (H256::repeat_byte(1), 5);
+		let justification =
+			crate::justification::make_test_justification::<Block>(target, &pairs, 2, 3);
+
+		let event = compact_finality_event(&justification, 2);
+		assert_eq!(
+			event,
+			CompactFinalityEvent { hash: target.0, number: 5, view: 3, set_id: 2, signer_count: 3 },
+		);
+		assert!(event.encode().len() < justification.encode().len());
+	}
+}
@@ -62,12 +62,18 @@ pub(crate) mod import;
 pub(crate) mod justification;
 pub(crate) mod notification;
 pub(crate) mod until_imported;
+pub(crate) mod validation;
 // pub mod warp_proof;
 
 pub use communication::tendermint_protocol_name::standard_name as protocol_standard_name;
 pub use import::TendermintBlockImport;
 pub use notification::{TendermintJustificationSender, TendermintJustificationStream};
 use until_imported::UntilGlobalMessageBlocksImported;
+pub use validation::{
+	check_lock_consistency, check_proposal_timing, dedup_equivocations,
+	detect_precommit_equivocations, verify_equivocation, verify_pol, verify_pol_certificate,
+	Equivocation,
+};
 
 /// A Tendermint message for a substrate chain.
 pub type Message<Block> = messages::Message<NumberFor<Block>, <Block as BlockT>::Hash>;
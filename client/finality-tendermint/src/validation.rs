@@ -0,0 +1,578 @@
+//! Rules for validating Tendermint proposals and votes against the local
+//! voter's state.
+
+use finality_tendermint::{messages, VoterSet};
+use sp_finality_tendermint::{AuthorityId, RoundNumber, SetId};
+use sp_runtime::traits::Block as BlockT;
+
+use crate::{AuthorityList, Error, SignedMessage};
+
+/// The number of authorities required to reach quorum in the given set, an empty
+/// set can never reach it.
+fn quorum_threshold(authorities: &AuthorityList) -> Result<usize, Error> {
+	VoterSet::new(authorities.to_vec())
+		.map(|voters| voters.threshold())
+		.ok_or_else(|| Error::Safety("no quorum can be reached in an empty authority set".into()))
+}
+
+/// Check that a proposal respects the value the local voter is locked on.
+///
+/// A voter that locked on a value in round `R` must only accept proposals for
+/// that value in later rounds, unless the proposal carries a proof-of-lock
+/// (`valid_round`) from a round higher than `R`, which proves that a quorum
+/// prevoted for the new value after we locked and thus unlocks us.
+pub fn check_lock_consistency<H: PartialEq>(
+	locked: Option<(RoundNumber, &H)>,
+	proposal_hash: &H,
+	proposal_valid_round: Option<RoundNumber>,
+) -> Result<(), Error> {
+	let (locked_round, locked_hash) = match locked {
+		Some(locked) => locked,
+		None => return Ok(()),
+	};
+
+	if locked_hash == proposal_hash {
+		return Ok(())
+	}
+
+	match proposal_valid_round {
+		Some(valid_round) if valid_round > locked_round => Ok(()),
+		_ => Err(Error::Safety(format!(
+			"proposal conflicts with value locked in round {} without a higher proof-of-lock",
+			locked_round,
+		))),
+	}
+}
+
+/// Check that the proof-of-lock claimed by a proposal is backed by evidence,
+/// i.e. the given prevotes contain a quorum of valid prevotes from distinct
+/// authorities for the proposed block in the claimed `pol_round`. A proposal
+/// that doesn't claim a proof-of-lock is always valid.
+pub fn verify_pol<Block: BlockT>(
+	proposal_hash: &Block::Hash,
+	pol_round: Option<RoundNumber>,
+	prevotes: &[SignedMessage<Block>],
+	authorities: &AuthorityList,
+	set_id: SetId,
+) -> Result<(), Error> {
+	let pol_round = match pol_round {
+		Some(pol_round) => pol_round,
+		None => return Ok(()),
+	};
+	let threshold = quorum_threshold(authorities)?;
+
+	let mut buf = Vec::new();
+	let mut voters = Vec::<&AuthorityId>::new();
+	for signed in prevotes {
+		let prevote = match &signed.message {
+			messages::Message::Prevote(prevote) => prevote,
+			_ => continue,
+		};
+
+		if prevote.target_hash.as_ref() != Some(proposal_hash) ||
+			!authorities.contains(&signed.id) ||
+			voters.contains(&&signed.id)
+		{
+			continue
+		}
+
+		if sp_finality_tendermint::check_message_signature_with_buffer(
+			&signed.message,
+			&signed.id,
+			&signed.signature,
+			pol_round,
+			set_id,
+			&mut buf,
+		) {
+			voters.push(&signed.id);
+		}
+	}
+
+	if voters.len() < threshold {
+		return Err(Error::Safety(format!(
+			"proposal claims a proof-of-lock in round {} without a quorum of prevotes",
+			pol_round,
+		)))
+	}
+
+	Ok(())
+}
+
+/// Verify that the given prevotes form a proof-of-lock for `round`, i.e. contain
+/// a quorum of valid prevotes from distinct authorities for a single value.
+/// Returns the locked block hash, or `None` if the quorum prevoted nil. Prevotes
+/// from outside the set, with an invalid signature or for another round are
+/// ignored, and only the first valid prevote of each authority is counted. Split
+/// prevotes where no single value reaches quorum are rejected.
+pub fn verify_pol_certificate<Block: BlockT>(
+	prevotes: &[SignedMessage<Block>],
+	round: RoundNumber,
+	authorities: &AuthorityList,
+	set_id: SetId,
+) -> Result<Option<Block::Hash>, Error> {
+	let mut buf = Vec::new();
+	let mut voters = Vec::<&AuthorityId>::new();
+	let mut tally = Vec::<(Option<Block::Hash>, usize)>::new();
+	for signed in prevotes {
+		let prevote = match &signed.message {
+			messages::Message::Prevote(prevote) => prevote,
+			_ => continue,
+		};
+
+		if !authorities.contains(&signed.id) || voters.contains(&&signed.id) {
+			continue
+		}
+
+		if !sp_finality_tendermint::check_message_signature_with_buffer(
+			&signed.message,
+			&signed.id,
+			&signed.signature,
+			round,
+			set_id,
+			&mut buf,
+		) {
+			continue
+		}
+
+		voters.push(&signed.id);
+		match tally.iter_mut().find(|(hash, _)| *hash == prevote.target_hash) {
+			Some((_, count)) => *count += 1,
+			None => tally.push((prevote.target_hash, 1)),
+		}
+	}
+
+	let threshold = quorum_threshold(authorities)?;
+	match tally.into_iter().find(|(_, count)| *count >= threshold) {
+		Some((hash, _)) => Ok(hash),
+		None => Err(Error::Safety(format!(
+			"no quorum of prevotes for a single value in round {}",
+			round,
+		))),
+	}
+}
+
+/// Find the authorities that precommitted to two different values at the same
+/// height in the given round, returning the first valid precommit seen from the
+/// authority at that height together with each conflicting one. A precommit for
+/// nil conflicts with a precommit for a block just as two precommits for
+/// different blocks do, since both count towards different quorums. Precommits
+/// from outside the set or with an invalid signature are ignored.
+pub fn detect_precommit_equivocations<Block: BlockT>(
+	precommits: &[SignedMessage<Block>],
+	round: RoundNumber,
+	authorities: &AuthorityList,
+	set_id: SetId,
+) -> Vec<(SignedMessage<Block>, SignedMessage<Block>)> {
+	let mut buf = Vec::new();
+	let mut seen = Vec::<&SignedMessage<Block>>::new();
+	let mut equivocations = Vec::new();
+	for signed in precommits {
+		let precommit = match &signed.message {
+			messages::Message::Precommit(precommit) => precommit,
+			_ => continue,
+		};
+
+		if !authorities.contains(&signed.id) ||
+			!sp_finality_tendermint::check_message_signature_with_buffer(
+				&signed.message,
+				&signed.id,
+				&signed.signature,
+				round,
+				set_id,
+				&mut buf,
+			) {
+			continue
+		}
+
+		// precommits for different heights don't compete for the same quorum.
+		let first = seen.iter().find(|first| {
+			first.id == signed.id &&
+				matches!(
+					&first.message,
+					messages::Message::Precommit(first)
+						if first.target_height == precommit.target_height
+				)
+		});
+
+		match first {
+			// at the same height, the precommits can only differ by their value.
+			Some(first) if first.message != signed.message =>
+				equivocations.push(((*first).clone(), signed.clone())),
+			Some(_) => {},
+			None => seen.push(signed),
+		}
+	}
+
+	equivocations
+}
+
+/// Check that the given precommits are an equivocation, i.e. precommits from the
+/// same authority of the set for different values at the same height, both
+/// validly signed for the given round and set.
+pub fn verify_equivocation<Block: BlockT>(
+	first: &SignedMessage<Block>,
+	second: &SignedMessage<Block>,
+	round: RoundNumber,
+	authorities: &AuthorityList,
+	set_id: SetId,
+) -> Result<(), Error> {
+	let (a, b) = match (&first.message, &second.message) {
+		(messages::Message::Precommit(a), messages::Message::Precommit(b)) => (a, b),
+		_ => return Err(Error::Safety("equivocation must be made of precommits".to_string())),
+	};
+
+	if first.id != second.id || !authorities.contains(&first.id) {
+		return Err(Error::Safety(
+			"equivocation must be made by a single authority of the set".to_string(),
+		))
+	}
+
+	if a.target_height != b.target_height {
+		return Err(Error::Safety(format!(
+			"precommits at heights {} and {} are not an equivocation",
+			a.target_height, b.target_height,
+		)))
+	}
+
+	if a.target_hash == b.target_hash {
+		return Err(Error::Safety(
+			"precommits for the same value are not an equivocation".to_string(),
+		))
+	}
+
+	let mut buf = Vec::new();
+	for signed in [first, second] {
+		if !sp_finality_tendermint::check_message_signature_with_buffer(
+			&signed.message,
+			&signed.id,
+			&signed.signature,
+			round,
+			set_id,
+			&mut buf,
+		) {
+			return Err(Error::Safety("invalid signature in equivocation".to_string()))
+		}
+	}
+
+	Ok(())
+}
+
+/// An equivocation found in the given set and round, see
+/// `detect_precommit_equivocations`.
+pub type Equivocation<Block> = (SetId, RoundNumber, (SignedMessage<Block>, SignedMessage<Block>));
+
+/// Drop redundant equivocations from the given batch before reporting it, i.e.
+/// keep only the first one for every `(offender, set_id, round)` so the same
+/// offense isn't punished twice. An authority voting for more than two values in
+/// a round is detected more than once, for instance.
+pub fn dedup_equivocations<Block: BlockT>(
+	equivocations: Vec<Equivocation<Block>>,
+) -> Vec<Equivocation<Block>> {
+	let mut offenses = Vec::<(AuthorityId, SetId, RoundNumber)>::new();
+	let mut deduped = Vec::new();
+	for (set_id, round, (first, second)) in equivocations {
+		let offense = (first.id.clone(), set_id, round);
+		if offenses.contains(&offense) {
+			continue
+		}
+
+		offenses.push(offense);
+		deduped.push((set_id, round, (first, second)));
+	}
+
+	deduped
+}
+
+/// Check that a proposal doesn't arrive before the previous block's commit time
+/// plus the minimum block interval. An honest proposer waits for the interval to
+/// elapse, an early proposal is a sign of a misbehaving proposer trying to shorten
+/// the round for everyone else.
+pub fn check_proposal_timing(
+	proposal_time_ms: u64,
+	prev_commit_time_ms: u64,
+	min_interval_ms: u64,
+) -> Result<(), Error> {
+	let earliest = prev_commit_time_ms.saturating_add(min_interval_ms);
+	if proposal_time_ms < earliest {
+		return Err(Error::Safety(format!(
+			"proposal at {}ms arrived before the earliest allowed time of {}ms",
+			proposal_time_ms, earliest,
+		)))
+	}
+
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use sp_core::H256;
+	use sp_keyring::Ed25519Keyring;
+	use substrate_test_runtime_client::runtime::Block;
+
+	/// A set of four authorities, along with their keys.
+	fn voters() -> ([Ed25519Keyring; 4], AuthorityList) {
+		let keyrings = [
+			Ed25519Keyring::Alice,
+			Ed25519Keyring::Bob,
+			Ed25519Keyring::Charlie,
+			Ed25519Keyring::Dave,
+		];
+		let authorities = keyrings.iter().map(|k| k.public().into()).collect();
+		(keyrings, authorities)
+	}
+
+	fn prevote(keyring: Ed25519Keyring, hash: H256, round: RoundNumber) -> SignedMessage<Block> {
+		let message = messages::Message::Prevote(messages::Prevote {
+			target_hash: Some(hash),
+			target_height: 1,
+		});
+		let payload = sp_finality_tendermint::localized_payload(round, 0, &message);
+
+		messages::SignedMessage {
+			message,
+			signature: keyring.sign(&payload[..]).into(),
+			id: keyring.public().into(),
+		}
+	}
+
+	/// The prevotes of the given authorities for the given hashes, in order.
+	fn prevotes(
+		keyrings: &[Ed25519Keyring],
+		hashes: &[H256],
+		round: RoundNumber,
+	) -> Vec<SignedMessage<Block>> {
+		keyrings.iter().zip(hashes).map(|(k, h)| prevote(*k, *h, round)).collect()
+	}
+
+	fn precommit(
+		keyring: Ed25519Keyring,
+		hash: Option<H256>,
+		height: u64,
+		round: RoundNumber,
+	) -> SignedMessage<Block> {
+		let message = messages::Message::Precommit(messages::Precommit {
+			target_hash: hash,
+			target_height: height,
+		});
+		let payload = sp_finality_tendermint::localized_payload(round, 0, &message);
+
+		messages::SignedMessage {
+			message,
+			signature: keyring.sign(&payload[..]).into(),
+			id: keyring.public().into(),
+		}
+	}
+
+	#[test]
+	fn lock_is_respected_unless_unlocked_by_higher_pol() {
+		let (a, b) = (1u8, 2u8);
+
+		// not locked, anything goes.
+		assert!(check_lock_consistency(None, &b, None).is_ok());
+
+		// locked on `a` in round 2, proposals for `a` are fine.
+		assert!(check_lock_consistency(Some((2, &a)), &a, None).is_ok());
+
+		// a different value without a proof-of-lock, or with a stale one, is rejected.
+		assert!(check_lock_consistency(Some((2, &a)), &b, None).is_err());
+		assert!(check_lock_consistency(Some((2, &a)), &b, Some(1)).is_err());
+		assert!(check_lock_consistency(Some((2, &a)), &b, Some(2)).is_err());
+
+		// a proof-of-lock from a higher round unlocks us.
+		assert!(check_lock_consistency(Some((2, &a)), &b, Some(3)).is_ok());
+	}
+
+	#[test]
+	fn proof_of_lock_requires_quorum_of_prevotes() {
+		let keyrings = [
+			Ed25519Keyring::Alice,
+			Ed25519Keyring::Bob,
+			Ed25519Keyring::Charlie,
+			Ed25519Keyring::Dave,
+		];
+		let authorities: AuthorityList = keyrings.iter().map(|k| k.public().into()).collect();
+		let (a, b) = (H256::repeat_byte(1), H256::repeat_byte(2));
+
+		let prevotes = |hashes: &[H256], round| {
+			keyrings
+				.iter()
+				.zip(hashes)
+				.map(|(k, h)| prevote(*k, *h, round))
+				.collect::<Vec<_>>()
+		};
+
+		// no proof-of-lock claimed.
+		assert!(verify_pol::<Block>(&a, None, &[], &authorities, 0).is_ok());
+
+		// 3 out of 4 prevotes for the proposed block in the claimed round.
+		let pol = prevotes(&[a, a, a, b], 2);
+		assert!(verify_pol::<Block>(&a, Some(2), &pol, &authorities, 0).is_ok());
+
+		// a fabricated round, or a split vote, doesn't prove a lock.
+		assert!(verify_pol::<Block>(&a, Some(3), &pol, &authorities, 0).is_err());
+		assert!(verify_pol::<Block>(&b, Some(2), &pol, &authorities, 0).is_err());
+		let split = prevotes(&[a, a, b, b], 2);
+		assert!(verify_pol::<Block>(&a, Some(2), &split, &authorities, 0).is_err());
+
+		// duplicated prevotes are only counted once.
+		let duplicated = prevotes(&[a, a], 2).into_iter().cycle().take(4).collect::<Vec<_>>();
+		assert!(verify_pol::<Block>(&a, Some(2), &duplicated, &authorities, 0).is_err());
+
+		// an empty set can't prove a lock, even though no prevote is needed.
+		assert!(verify_pol::<Block>(&a, Some(2), &[], &Vec::new(), 0).is_err());
+	}
+
+	#[test]
+	fn early_proposals_are_rejected() {
+		assert!(check_proposal_timing(1_500, 1_000, 1_000).is_err());
+		assert!(check_proposal_timing(1_999, 1_000, 1_000).is_err());
+		assert!(check_proposal_timing(2_000, 1_000, 1_000).is_ok());
+		assert!(check_proposal_timing(5_000, 1_000, 1_000).is_ok());
+
+		// the earliest allowed time saturates rather than wrapping around.
+		assert!(check_proposal_timing(u64::MAX - 1, u64::MAX, 1_000).is_err());
+	}
+
+	#[test]
+	fn pol_certificate_requires_quorum_for_a_single_value() {
+		let (keyrings, authorities) = voters();
+		let (a, b) = (H256::repeat_byte(1), H256::repeat_byte(2));
+		let prevotes = |hashes: &[H256], round| prevotes(&keyrings, hashes, round);
+
+		let pol = prevotes(&[a, a, b, a], 2);
+		assert_eq!(verify_pol_certificate::<Block>(&pol, 2, &authorities, 0).unwrap(), Some(a));
+
+		// signatures are localized to the round.
+		assert!(verify_pol_certificate::<Block>(&pol, 3, &authorities, 0).is_err());
+
+		// split prevotes don't form a proof-of-lock.
+		let split = prevotes(&[a, a, b, b], 2);
+		assert!(verify_pol_certificate::<Block>(&split, 2, &authorities, 0).is_err());
+
+		// a quorum of nil prevotes.
+		let nil = keyrings
+			.iter()
+			.map(|k| {
+				let message = messages::Message::Prevote(messages::Prevote {
+					target_hash: None,
+					target_height: 1,
+				});
+				let payload = sp_finality_tendermint::localized_payload(2, 0, &message);
+
+				messages::SignedMessage {
+					message,
+					signature: k.sign(&payload[..]).into(),
+					id: k.public().into(),
+				}
+			})
+			.collect::<Vec<SignedMessage<Block>>>();
+		assert_eq!(verify_pol_certificate::<Block>(&nil, 2, &authorities, 0).unwrap(), None);
+	}
+
+	#[test]
+	fn precommit_for_nil_and_block_is_an_equivocation() {
+		let keyrings = [Ed25519Keyring::Alice, Ed25519Keyring::Bob, Ed25519Keyring::Charlie];
+		let authorities: AuthorityList = keyrings.iter().map(|k| k.public().into()).collect();
+		let a = H256::repeat_byte(1);
+
+		let precommit = |keyring: Ed25519Keyring, hash: Option<H256>| {
+			let message = messages::Message::Precommit(messages::Precommit {
+				target_hash: hash,
+				target_height: 1,
+			});
+			let payload = sp_finality_tendermint::localized_payload(2, 0, &message);
+
+			messages::SignedMessage {
+				message,
+				signature: keyring.sign(&payload[..]).into(),
+				id: keyring.public().into(),
+			}
+		};
+
+		let precommits: Vec<SignedMessage<Block>> = vec![
+			precommit(Ed25519Keyring::Alice, Some(a)),
+			precommit(Ed25519Keyring::Bob, Some(a)),
+			precommit(Ed25519Keyring::Alice, None),
+			// repeating the same precommit isn't an equivocation.
+			precommit(Ed25519Keyring::Bob, Some(a)),
+		];
+
+		let equivocations = detect_precommit_equivocations(&precommits, 2, &authorities, 0);
+		assert_eq!(equivocations.len(), 1);
+		assert_eq!(equivocations[0].0.id, authorities[0]);
+		assert!(matches!(
+			&equivocations[0].1.message,
+			messages::Message::Precommit(messages::Precommit { target_hash: None, .. })
+		));
+
+		// signatures are localized to the round.
+		assert!(detect_precommit_equivocations(&precommits, 3, &authorities, 0).is_empty());
+	}
+
+	#[test]
+	fn duplicate_offenses_are_dropped_from_batch() {
+		let (a, b, c) = (H256::repeat_byte(1), H256::repeat_byte(2), H256::repeat_byte(3));
+		let pair = |keyring, first, second, round| {
+			(prevote(keyring, first, round), prevote(keyring, second, round))
+		};
+
+		let batch: Vec<Equivocation<Block>> = vec![
+			(0, 2, pair(Ed25519Keyring::Alice, a, b, 2)),
+			// the same offense, detected again through a third vote.
+			(0, 2, pair(Ed25519Keyring::Alice, a, c, 2)),
+			// offenses in another round or set, or by another authority are kept.
+			(0, 3, pair(Ed25519Keyring::Alice, a, b, 3)),
+			(1, 2, pair(Ed25519Keyring::Alice, a, b, 2)),
+			(0, 2, pair(Ed25519Keyring::Bob, a, b, 2)),
+		];
+
+		let deduped = dedup_equivocations(batch.clone());
+		assert_eq!(deduped.len(), 4);
+		assert_eq!(deduped[0], batch[0]);
+		assert_eq!(&deduped[1..], &batch[2..]);
+	}
+
+	#[test]
+	fn precommits_at_different_heights_are_not_an_equivocation() {
+		let authorities: AuthorityList = vec![Ed25519Keyring::Alice.public().into()];
+		let precommit = |hash, height| precommit(Ed25519Keyring::Alice, hash, height, 2);
+
+		let (a, b) = (Some(H256::repeat_byte(1)), Some(H256::repeat_byte(2)));
+		let first = precommit(a, 1);
+		assert!(verify_equivocation(&first, &precommit(b, 1), 2, &authorities, 0).is_ok());
+		assert!(verify_equivocation(&first, &precommit(None, 1), 2, &authorities, 0).is_ok());
+
+		assert!(matches!(
+			verify_equivocation(&first, &precommit(b, 2), 2, &authorities, 0),
+			Err(Error::Safety(msg)) if msg.contains("heights")
+		));
+		assert!(verify_equivocation(&first, &precommit(a, 1), 2, &authorities, 0).is_err());
+		assert!(verify_equivocation(&first, &precommit(b, 1), 3, &authorities, 0).is_err());
+
+		// and they aren't detected as one either, while conflicts at each height are.
+		let precommits = vec![first.clone(), precommit(b, 2), precommit(b, 1), precommit(None, 2)];
+		let equivocations = detect_precommit_equivocations(&precommits, 2, &authorities, 0);
+		assert_eq!(
+			equivocations,
+			vec![(first, precommit(b, 1)), (precommit(b, 2), precommit(None, 2))]
+		);
+	}
+
+	#[test]
+	fn equivocation_is_detected_among_precommits_at_the_same_height() {
+		let authorities: AuthorityList = vec![Ed25519Keyring::Alice.public().into()];
+		let (a, b, c) = (H256::repeat_byte(1), H256::repeat_byte(2), H256::repeat_byte(3));
+
+		// the first precommit is at another height, it must not hide the conflict
+		// between the later ones.
+		let precommits = vec![
+			precommit(Ed25519Keyring::Alice, Some(a), 1, 2),
+			precommit(Ed25519Keyring::Alice, Some(b), 2, 2),
+			precommit(Ed25519Keyring::Alice, Some(c), 2, 2),
+		];
+
+		let equivocations = detect_precommit_equivocations(&precommits, 2, &authorities, 0);
+		assert_eq!(equivocations, vec![(precommits[1].clone(), precommits[2].clone())]);
+	}
+}
@@ -80,6 +80,12 @@ impl<T: Config> Pallet<T> {
 		storage::unhashed::put(PBFT_AUTHORITIES_KEY, &authorities);
 	}
 
+	/// Wrap the given PBFT consensus log into a digest item, e.g. to deposit it
+	/// in the block's digests.
+	pub fn pbft_log(log: ConsensusLog<T::BlockNumber>) -> DigestItem {
+		DigestItem::Consensus(PBFT_ENGINE_ID, log.encode())
+	}
+
 	// Perform module initialization, abstracted so that it can be called either through genesis
 	// config builder or through `on_genesis_session`.
 	fn initialize(authorities: &AuthorityList) {
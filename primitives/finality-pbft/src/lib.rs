@@ -11,8 +11,11 @@ use scale_info::TypeInfo;
 
 #[cfg(feature = "std")]
 use sp_keystore::{SyncCryptoStore, SyncCryptoStorePtr};
-use sp_runtime::{traits::NumberFor, ConsensusEngineId, RuntimeDebug};
-use sp_std::{borrow::Cow, vec::Vec};
+use sp_runtime::{
+	traits::{NumberFor, Saturating},
+	ConsensusEngineId, RuntimeDebug,
+};
+use sp_std::{borrow::Cow, collections::btree_map::BTreeMap, vec::Vec};
 
 #[cfg(feature = "std")]
 use log::debug;
@@ -111,6 +114,10 @@ pub enum ConsensusLog<N: Codec> {
 	/// After authoring the block at _delay_ the authorities should resume voting.
 	#[codec(index = 5)]
 	Resume(N),
+	/// Note that the authority with given index in the given set was reported
+	/// for equivocating.
+	#[codec(index = 6)]
+	EquivocationReport(SetId, AuthorityIndex),
 }
 
 impl<N: Codec> ConsensusLog<N> {
@@ -147,6 +154,300 @@ impl<N: Codec> ConsensusLog<N> {
 	}
 }
 
+/// Create the consensus log noting that the authority with the given index in
+/// the given set was reported for equivocating.
+pub fn equivocation_report_log<N: Codec>(set_id: SetId, index: AuthorityIndex) -> ConsensusLog<N> {
+	ConsensusLog::EquivocationReport(set_id, index)
+}
+
+/// The maximum number of faulty authorities tolerated by a set of `n` authorities.
+pub fn fault_tolerance(n: usize) -> usize {
+	n.saturating_sub(1) / 3
+}
+
+/// The number of authorities required to reach quorum in a set of `n` authorities.
+pub fn quorum_threshold(n: usize) -> usize {
+	n - fault_tolerance(n)
+}
+
+/// How close a set of authorities is to losing safety as authorities get disabled.
+#[derive(Clone, Copy, Eq, PartialEq, RuntimeDebug)]
+pub enum SafetyLevel {
+	/// More than a quorum of authorities is still enabled, the set can
+	/// tolerate further disabled authorities.
+	Safe,
+	/// Exactly a quorum of authorities is still enabled, disabling any other
+	/// authority halts finality.
+	Marginal,
+	/// Less than a quorum of authorities is still enabled.
+	Unsafe,
+}
+
+/// Get the safety level of a set of `n` authorities with `disabled` of them
+/// disabled. The quorum is always the one of the original set, i.e. disabled
+/// authorities still count towards `n` when computing the threshold.
+pub fn safety_margin(n: usize, disabled: usize) -> SafetyLevel {
+	let enabled = n.saturating_sub(disabled);
+	let threshold = quorum_threshold(n);
+
+	if enabled > threshold {
+		SafetyLevel::Safe
+	} else if enabled == threshold {
+		SafetyLevel::Marginal
+	} else {
+		SafetyLevel::Unsafe
+	}
+}
+
+/// The number of views that went by since the view in which a block was last
+/// finalized. A growing value means view changes keep happening without the
+/// network making any progress.
+pub fn views_since_finality(current_view: ViewNumber, last_finalized_view: ViewNumber) -> u64 {
+	current_view.saturating_sub(last_finalized_view)
+}
+
+/// The weighted median of the given `(finalized_number, weight)` reports, i.e.
+/// the lowest reported block number at which the cumulative weight of the reports
+/// at or below it reaches half of the total weight. Returns `None` if there are no
+/// reports or they carry no weight.
+pub fn weighted_median_finalized<N: Ord + Copy>(reports: &[(N, u64)]) -> Option<N> {
+	let total = reports.iter().map(|(_, weight)| *weight as u128).sum::<u128>();
+	if total == 0 {
+		return None
+	}
+
+	let mut reports = reports.to_vec();
+	reports.sort_by_key(|(number, _)| *number);
+
+	let mut cumulative = 0u128;
+	for (number, weight) in reports {
+		cumulative += weight as u128;
+		if cumulative * 2 >= total {
+			return Some(number)
+		}
+	}
+
+	None
+}
+
+/// The views missing from the `[min, max]` range of the given observed views, in
+/// ascending order. Large gaps in the views of buffered messages point at dropped
+/// gossip. The result holds up to `max - min` views, so the observed views should
+/// come from a bounded window (e.g. the views we currently keep messages for).
+pub fn view_gaps(observed: &[ViewNumber]) -> Vec<ViewNumber> {
+	let mut observed = observed.to_vec();
+	observed.sort_unstable();
+	observed.dedup();
+
+	observed.windows(2).flat_map(|pair| (pair[0] + 1)..pair[1]).collect()
+}
+
+/// The number of blocks finality trails behind the best block, zero if the
+/// best block is finalized (e.g. both are genesis).
+pub fn finality_lag<N: Saturating>(best: N, finalized: N) -> N {
+	best.saturating_sub(finalized)
+}
+
+/// Get the raw ed25519 public key bytes of the given authority, e.g. to export it
+/// to tooling outside of substrate.
+pub fn authority_id_bytes(id: &AuthorityId) -> [u8; 32] {
+	sp_core::ed25519::Public::from(id.clone()).0
+}
+
+/// Construct an authority id from raw ed25519 public key bytes. This is the
+/// inverse of [`authority_id_bytes`].
+pub fn authority_id_from_bytes(bytes: [u8; 32]) -> AuthorityId {
+	sp_core::ed25519::Public::from_raw(bytes).into()
+}
+
+/// Sort the given authorities in canonical order, i.e. by ascending raw public key
+/// bytes. All PBFT authorities vote with the same weight, hence the public key is
+/// the only ordering criterion. This is the ordering that must be used whenever
+/// nodes need to agree on authority positions independently of the order the
+/// runtime provided them in (e.g. participation bitfields or commitments).
+pub fn canonical_order(authorities: &mut AuthorityList) {
+	authorities.sort_by_key(authority_id_bytes);
+}
+
+/// The number of proposals the given authority is expected to make over `windows`
+/// views, to be compared against the number it actually made (e.g. to detect a
+/// censored proposer). PBFT authorities all vote with the same weight, so every
+/// member is expected to propose an equal share. Zero if `id` is not part of the
+/// set.
+#[cfg(feature = "std")]
+pub fn expected_proposals(id: &AuthorityId, authorities: &AuthorityList, windows: u64) -> f64 {
+	if !authorities.contains(id) {
+		return 0.0
+	}
+
+	windows as f64 / authorities.len() as f64
+}
+
+/// Map the positions of the given canonically ordered authorities (see
+/// `canonical_order`) to their positions in the order the runtime provided them
+/// in, i.e. the `i`-th entry of the result is the on-chain index of
+/// `canonical[i]`. On-chain indices are used by `ConsensusLog::OnDisabled`, this
+/// allows applying them to a canonically ordered set. Returns `None` if the two
+/// lists don't contain the same authorities.
+pub fn index_remap(
+	onchain: &AuthorityList,
+	canonical: &AuthorityList,
+) -> Option<Vec<AuthorityIndex>> {
+	if onchain.len() != canonical.len() {
+		return None
+	}
+
+	canonical
+		.iter()
+		.map(|id| {
+			onchain
+				.iter()
+				.position(|other| other == id)
+				.map(|index| index as AuthorityIndex)
+		})
+		.collect()
+}
+
+/// Whether the new authority set shares at least `min_overlap` members with the
+/// old one. Some deployments require e.g. `f + 1` common members on every handoff
+/// so that the old set can't on its own rewrite what the new set finalizes, the
+/// runtime can check this when validating a proposed `ScheduledChange`. This is a
+/// deployment policy, the protocol itself doesn't require any overlap.
+pub fn change_preserves_overlap(
+	old: &AuthorityList,
+	new: &AuthorityList,
+	min_overlap: usize,
+) -> bool {
+	let mut common = old.iter().filter(|id| new.contains(id)).collect::<Vec<_>>();
+	common.sort_by_key(|id| authority_id_bytes(id));
+	common.dedup();
+
+	common.len() >= min_overlap
+}
+
+/// The members of the given set that are not among `known_signers`, i.e. the
+/// authorities that are likely to hold the commit signatures we're missing and
+/// should thus be solicited when catching up. PBFT authorities all vote with the
+/// same weight, so there's no priority among them and the set order is kept.
+pub fn catchup_candidates(
+	authorities: &AuthorityList,
+	known_signers: &[AuthorityId],
+) -> Vec<AuthorityId> {
+	authorities.iter().filter(|id| !known_signers.contains(id)).cloned().collect()
+}
+
+/// Compute the Merkle root of the given authority list.
+///
+/// The leaves are the `blake2_256` hashes of the raw public keys, in list order.
+/// Each level is built by hashing the concatenation of every pair of nodes, an
+/// odd node at the end of a level is promoted unchanged. The root of an empty
+/// list is all zeros.
+pub fn authorities_merkle_root(authorities: &AuthorityList) -> [u8; 32] {
+	let mut level = authorities.iter().map(authority_merkle_leaf).collect::<Vec<_>>();
+
+	if level.is_empty() {
+		return [0; 32]
+	}
+
+	while level.len() > 1 {
+		level = merkle_parent_level(&level);
+	}
+
+	level[0]
+}
+
+fn authority_merkle_leaf(id: &AuthorityId) -> [u8; 32] {
+	sp_core::hashing::blake2_256(&authority_id_bytes(id))
+}
+
+fn merkle_node(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+	sp_core::hashing::blake2_256(&[&left[..], &right[..]].concat())
+}
+
+fn merkle_parent_level(level: &[[u8; 32]]) -> Vec<[u8; 32]> {
+	level
+		.chunks(2)
+		.map(|pair| match pair {
+			[left, right] => merkle_node(left, right),
+			[single] => *single,
+			_ => unreachable!("chunks are of size at most 2; qed"),
+		})
+		.collect()
+}
+
+/// A proof that an authority is part of the list committed to by a root
+/// computed with `authorities_merkle_root`.
+#[derive(Clone, Eq, PartialEq, Encode, Decode, RuntimeDebug, TypeInfo)]
+pub struct MerkleProof {
+	/// The position of the authority in the list.
+	pub index: u32,
+	/// The number of authorities in the list.
+	pub leaf_count: u32,
+	/// The sibling nodes from the leaf level up to the root. Levels where the
+	/// node is promoted unchanged (i.e. it has no sibling) are skipped.
+	pub siblings: Vec<[u8; 32]>,
+}
+
+/// Generate a proof that the authority at the given index is part of the list,
+/// `None` if the index is out of bounds.
+pub fn authority_merkle_proof(authorities: &AuthorityList, index: u32) -> Option<MerkleProof> {
+	let mut level = authorities.iter().map(authority_merkle_leaf).collect::<Vec<_>>();
+	if index as usize >= level.len() {
+		return None
+	}
+
+	let mut siblings = Vec::new();
+	let mut position = index as usize;
+	while level.len() > 1 {
+		if position % 2 == 1 {
+			siblings.push(level[position - 1]);
+		} else if position + 1 < level.len() {
+			siblings.push(level[position + 1]);
+		}
+
+		level = merkle_parent_level(&level);
+		position /= 2;
+	}
+
+	Some(MerkleProof { index, leaf_count: authorities.len() as u32, siblings })
+}
+
+/// Check that the given authority is part of the list committed to by `root`.
+pub fn verify_authority_membership(id: &AuthorityId, proof: &MerkleProof, root: [u8; 32]) -> bool {
+	if proof.index >= proof.leaf_count {
+		return false
+	}
+
+	let mut hash = authority_merkle_leaf(id);
+	let mut siblings = proof.siblings.iter();
+	let (mut position, mut width) = (proof.index, proof.leaf_count);
+	while width > 1 {
+		if position % 2 == 1 {
+			match siblings.next() {
+				Some(sibling) => hash = merkle_node(sibling, &hash),
+				None => return false,
+			}
+		} else if position + 1 < width {
+			match siblings.next() {
+				Some(sibling) => hash = merkle_node(&hash, sibling),
+				None => return false,
+			}
+		}
+
+		position /= 2;
+		width = (width + 1) / 2;
+	}
+
+	siblings.next().is_none() && hash == root
+}
+
+/// Check that the given authorities match the expected Merkle root, e.g. one baked
+/// into the node for the genesis authority set, so that a node started with the
+/// wrong genesis set fails fast.
+pub fn verify_genesis_authorities(authorities: &AuthorityList, expected_root: [u8; 32]) -> bool {
+	authorities_merkle_root(authorities) == expected_root
+}
+
 /// Encode round message localized to a given round and set id.
 pub fn localized_payload<E: Encode>(view: u64, set_id: SetId, message: &E) -> Vec<u8> {
 	let mut buf = Vec::new();
@@ -167,6 +468,52 @@ pub fn localized_payload_with_buffer<E: Encode>(
 	(message, view, set_id).encode_to(buf)
 }
 
+/// The length of the localized payload (see `localized_payload`) for the given
+/// message, computed without encoding it. Useful to pre-size a reusable buffer.
+pub fn localized_payload_len<E: Encode>(view: u64, set_id: SetId, message: &E) -> usize {
+	(message, view, set_id).encoded_size()
+}
+
+/// Encode round message localized to a given round and set id, prefixed with
+/// `PBFT_ENGINE_ID` as a domain tag.
+///
+/// The untagged payload (`localized_payload`) only commits to the message, view
+/// and set id, so a signature over it could be replayed as a valid signature for
+/// another gadget whose payloads happen to encode the same way when the same key
+/// is used for both. Signatures over the tagged payload are only ever valid for
+/// PBFT, and are never valid over the untagged payload (nor the other way round).
+pub fn localized_payload_with_engine<E: Encode>(view: u64, set_id: SetId, message: &E) -> Vec<u8> {
+	(PBFT_ENGINE_ID, message, view, set_id).encode()
+}
+
+/// The encoded phase tag (i.e. SCALE variant index) of a pre-prepare message.
+pub const PRE_PREPARE_PHASE: u8 = 0;
+/// The encoded phase tag (i.e. SCALE variant index) of a prepare message.
+pub const PREPARE_PHASE: u8 = 1;
+/// The encoded phase tag (i.e. SCALE variant index) of a commit message.
+pub const COMMIT_PHASE: u8 = 2;
+
+/// Whether the given byte is the phase tag of a known PBFT message, i.e. the first
+/// byte of an encoded `leader::Message`. Decoding a message with any other tag
+/// fails with a codec error, this allows rejecting such a message (e.g. from a
+/// future protocol version) before attempting to decode it.
+pub fn is_known_phase(tag: u8) -> bool {
+	matches!(tag, PRE_PREPARE_PHASE | PREPARE_PHASE | COMMIT_PHASE)
+}
+
+/// A sane default for the maximum encoded size of a single PBFT message. This
+/// matches the maximum notification size of the PBFT gossip protocol, no valid
+/// message can be larger than that. The gossip validator checks the raw size
+/// of incoming data against it before decoding or verifying any signature.
+pub const MAX_MESSAGE_SIZE: usize = 1024 * 1024;
+
+/// An estimate of the memory used by the given buffered messages, i.e. the sum
+/// of their encoded sizes. This ignores allocator and container overhead, it is
+/// meant to help tuning how many messages a node buffers rather than to be exact.
+pub fn memory_estimate<M: Encode>(messages: &[M]) -> usize {
+	messages.iter().map(Encode::encoded_size).sum()
+}
+
 /// Check a message signature by encoding the message as a localized payload and
 /// verifying the provided signature using the expected authority id.
 pub fn check_message_signature<H, N>(
@@ -199,11 +546,124 @@ where
 	H: Encode,
 	N: Encode,
 {
-	use sp_application_crypto::RuntimeAppPublic;
-
 	localized_payload_with_buffer(view, set_id, message, buf);
 
-	let valid = id.verify(&buf, signature);
+	check_signature_over_payload(buf, id, signature)
+}
+
+/// Check a message signature by encoding the message as an engine tagged
+/// localized payload (see `localized_payload_with_engine`) and verifying the
+/// provided signature using the expected authority id. Signatures over the
+/// untagged payload are rejected.
+pub fn check_message_signature_with_engine<H, N>(
+	message: &leader::Message<H, N>,
+	id: &AuthorityId,
+	signature: &AuthoritySignature,
+	view: u64,
+	set_id: SetId,
+) -> bool
+where
+	H: Encode,
+	N: Encode,
+{
+	let payload = localized_payload_with_engine(view, set_id, message);
+
+	check_signature_over_payload(&payload, id, signature)
+}
+
+/// Check whether no block can reach quorum anymore given the votes cast so far in
+/// a phase of a view, i.e. the prepares or commits received from the set. This is
+/// the case once votes are split such that even if every authority that didn't
+/// vote yet voted for the leading block, it would still fall short of quorum, and
+/// the view can be abandoned early. Only the first vote of each member of the set
+/// is counted, pre-prepares are ignored and signatures are not checked.
+pub fn quorum_impossible<N, H: Ord>(
+	votes: &[(AuthorityId, leader::Message<N, H>)],
+	authorities: &AuthorityList,
+) -> bool {
+	let mut voted = Vec::<&AuthorityId>::new();
+	let mut targets = BTreeMap::<&H, usize>::new();
+	for (id, message) in votes {
+		let target_hash = match message {
+			leader::Message::Prepare(prepare) => &prepare.target_hash,
+			leader::Message::Commit(commit) => &commit.target_hash,
+			_ => continue,
+		};
+
+		if !authorities.contains(id) || voted.contains(&id) {
+			continue
+		}
+
+		voted.push(id);
+		*targets.entry(target_hash).or_default() += 1;
+	}
+
+	let leading = targets.values().max().copied().unwrap_or(0);
+	let remaining = authorities.len() - voted.len();
+	leading + remaining < quorum_threshold(authorities.len())
+}
+
+/// Find the target block reaching quorum among the given, possibly noisy, signed
+/// commits, e.g. to salvage a valid commit from a set mixing commits for different
+/// blocks. Commits are grouped by target hash, only commits from distinct members
+/// of the set with a valid signature for the given view and set id are counted.
+/// Returns `None` if no target, or more than one, reaches quorum.
+pub fn dominant_commit_target<N, H>(
+	commits: &[(AuthorityId, AuthoritySignature, leader::Message<N, H>)],
+	authorities: &AuthorityList,
+	view: u64,
+	set_id: SetId,
+) -> Option<H>
+where
+	H: Ord + Clone + Encode,
+	N: Encode,
+{
+	let mut buf = Vec::new();
+	let mut targets = Vec::<(&H, Vec<&AuthorityId>)>::new();
+	for (id, signature, message) in commits {
+		let target_hash = match message {
+			leader::Message::Commit(commit) => &commit.target_hash,
+			_ => continue,
+		};
+
+		if !authorities.contains(id) {
+			continue
+		}
+
+		let position = targets.iter().position(|(hash, _)| *hash == target_hash);
+		if position.map_or(false, |position| targets[position].1.contains(&id)) {
+			continue
+		}
+
+		if !check_message_signature_with_buffer(message, id, signature, view, set_id, &mut buf) {
+			continue
+		}
+
+		match position {
+			Some(position) => targets[position].1.push(id),
+			None => targets.push((target_hash, sp_std::vec![id])),
+		}
+	}
+
+	let threshold = quorum_threshold(authorities.len());
+	let mut reaching = targets.into_iter().filter(|(_, signers)| signers.len() >= threshold);
+	match (reaching.next(), reaching.next()) {
+		(Some((hash, _)), None) => Some(hash.clone()),
+		_ => None,
+	}
+}
+
+/// Verify the provided signature over an already localized payload (i.e. as
+/// returned by `localized_payload`) using the expected authority id. This is
+/// useful to avoid re-encoding the message when the payload is at hand.
+pub fn check_signature_over_payload(
+	payload: &[u8],
+	id: &AuthorityId,
+	signature: &AuthoritySignature,
+) -> bool {
+	use sp_application_crypto::RuntimeAppPublic;
+
+	let valid = id.verify(&payload, signature);
 
 	if !valid {
 		#[cfg(feature = "std")]
@@ -226,22 +686,306 @@ where
 	H: Encode,
 	N: Encode,
 {
+	let encoded = localized_payload(view, set_id, &message);
+	let signature = sign_payload(keystore, &public, &encoded)?;
+
+	Some(leader::SignedMessage { message, signature, id: public })
+}
+
+/// Signs an already localized payload with the given authority key.
+#[cfg(feature = "std")]
+fn sign_payload(
+	keystore: SyncCryptoStorePtr,
+	public: &AuthorityId,
+	payload: &[u8],
+) -> Option<AuthoritySignature> {
 	use sp_application_crypto::AppKey;
 	use sp_core::crypto::Public;
 
-	let encoded = localized_payload(view, set_id, &message);
-	let signature = SyncCryptoStore::sign_with(
+	SyncCryptoStore::sign_with(
 		&*keystore,
 		AuthorityId::ID,
 		&public.to_public_crypto_pair(),
-		&encoded[..],
+		payload,
 	)
 	.ok()
 	.flatten()?
 	.try_into()
-	.ok()?;
+	.ok()
+}
 
-	Some(leader::SignedMessage { message, signature, id: public })
+/// Build and sign the pre-prepare proposing the given target block, as the
+/// primary of the given view. Like all votes, the pre-prepare is signed over its
+/// payload localized to the view and set id.
+#[cfg(feature = "std")]
+pub fn make_preprepare<H, N>(
+	keystore: SyncCryptoStorePtr,
+	primary: AuthorityId,
+	view: ViewNumber,
+	set_id: SetId,
+	target: (H, N),
+) -> Option<leader::SignedMessage<N, H, AuthoritySignature, AuthorityId>>
+where
+	H: Encode,
+	N: Encode,
+{
+	let (target_hash, target_number) = target;
+	let message = leader::Message::PrePrepare(leader::PrePrepare { target_hash, target_number });
+
+	sign_message(keystore, message, primary, view, set_id)
+}
+
+/// Like `make_preprepare`, but signs the pre-prepare over its engine tagged
+/// payload (see `localized_payload_with_engine`), so the proposal can't be
+/// replayed to another gadget using the same key. Check it with
+/// `is_valid_primary_with_engine`.
+#[cfg(feature = "std")]
+pub fn make_preprepare_with_engine<H, N>(
+	keystore: SyncCryptoStorePtr,
+	primary: AuthorityId,
+	view: ViewNumber,
+	set_id: SetId,
+	target: (H, N),
+) -> Option<leader::SignedMessage<N, H, AuthoritySignature, AuthorityId>>
+where
+	H: Encode,
+	N: Encode,
+{
+	let (target_hash, target_number) = target;
+	let message = leader::Message::PrePrepare(leader::PrePrepare { target_hash, target_number });
+
+	let encoded = localized_payload_with_engine(view, set_id, &message);
+	let signature = sign_payload(keystore, &primary, &encoded)?;
+
+	Some(leader::SignedMessage { message, signature, id: primary })
+}
+
+/// Check that the given message is a pre-prepare signed by the primary of the
+/// given view (see `primary_for_view`), and that its signature is valid for the
+/// view and set id.
+pub fn is_valid_primary<N, H>(
+	signed: &leader::SignedMessage<N, H, AuthoritySignature, AuthorityId>,
+	view: ViewNumber,
+	set_id: SetId,
+	authorities: &AuthorityList,
+) -> bool
+where
+	N: Encode,
+	H: Encode,
+{
+	if !matches!(signed.message, leader::Message::PrePrepare(_)) {
+		return false
+	}
+
+	primary_for_view(view, authorities) == Some(&signed.id) &&
+		check_message_signature(&signed.message, &signed.id, &signed.signature, view, set_id)
+}
+
+/// Like `is_valid_primary`, but checks the signature over the engine tagged
+/// payload (see `make_preprepare_with_engine`).
+pub fn is_valid_primary_with_engine<N, H>(
+	signed: &leader::SignedMessage<N, H, AuthoritySignature, AuthorityId>,
+	view: ViewNumber,
+	set_id: SetId,
+	authorities: &AuthorityList,
+) -> bool
+where
+	N: Encode,
+	H: Encode,
+{
+	if !matches!(signed.message, leader::Message::PrePrepare(_)) {
+		return false
+	}
+
+	primary_for_view(view, authorities) == Some(&signed.id) &&
+		check_message_signature_with_engine(
+			&signed.message,
+			&signed.id,
+			&signed.signature,
+			view,
+			set_id,
+		)
+}
+
+/// A summary of the tenure of an authority set, e.g. for chain explorers to
+/// render the history of the validator set.
+#[cfg_attr(feature = "std", derive(Serialize))]
+#[derive(Clone, Eq, PartialEq, Encode, Decode, RuntimeDebug)]
+pub struct SetSummary<N> {
+	/// The id of the set.
+	pub set_id: SetId,
+	/// The number of authorities in the set.
+	pub member_count: u32,
+	/// The total voting weight of the set. PBFT authorities all vote with a
+	/// weight of one, so this is the number of authorities.
+	pub total_weight: u64,
+	/// The block at which the set was activated.
+	pub activated_at: N,
+	/// The block at which the set was superseded, if it was.
+	pub superseded_at: Option<N>,
+}
+
+/// Summarize the tenure of the given authority set.
+pub fn set_summary<N: Copy>(
+	set_id: SetId,
+	authorities: &AuthorityList,
+	activated_at: N,
+	superseded_at: Option<N>,
+) -> SetSummary<N> {
+	SetSummary {
+		set_id,
+		member_count: authorities.len() as u32,
+		total_weight: authorities.len() as u64,
+		activated_at,
+		superseded_at,
+	}
+}
+
+/// Get the primary of the given view, i.e. the authority at index `view % n` of
+/// the set, or `None` if the set is empty.
+pub fn primary_for_view(view: ViewNumber, authorities: &AuthorityList) -> Option<&AuthorityId> {
+	if authorities.is_empty() {
+		return None
+	}
+
+	authorities.get((view % authorities.len() as u64) as usize)
+}
+
+/// List the views among the `count` views starting at `from_view` in which one of
+/// the given local keys is the primary, along with the key to sign the proposal
+/// with. This allows a validator to prepare its proposals in advance.
+pub fn upcoming_primary_views(
+	local_keys: &[AuthorityId],
+	authorities: &AuthorityList,
+	from_view: ViewNumber,
+	count: u64,
+) -> Vec<(ViewNumber, AuthorityId)> {
+	(from_view..from_view.saturating_add(count))
+		.filter_map(|view| {
+			primary_for_view(view, authorities)
+				.filter(|primary| local_keys.contains(primary))
+				.map(|primary| (view, primary.clone()))
+		})
+		.collect()
+}
+
+/// Check whether the view change to `target_view` stalled, i.e. a quorum of view
+/// changes for it was seen but no new view arrived within the given timeout. The
+/// primary of the target view is then likely faulty and the next view should be
+/// moved to.
+#[cfg(feature = "std")]
+pub fn view_change_stalled(
+	target_view: ViewNumber,
+	have_quorum_vcs: bool,
+	elapsed: std::time::Duration,
+	timeout: std::time::Duration,
+) -> bool {
+	let stalled = have_quorum_vcs && elapsed >= timeout;
+	if stalled {
+		debug!(target: "afg", "View change to view {} stalled after {:?}", target_view, elapsed);
+	}
+
+	stalled
+}
+
+/// The quorum of the given authority set as a percentage of the set, e.g. to be
+/// displayed on dashboards. Authorities all vote with the same weight, so this is
+/// `quorum_threshold(n) / n`. Zero for an empty set.
+#[cfg(feature = "std")]
+pub fn quorum_percentage(authorities: &AuthorityList) -> f64 {
+	if authorities.is_empty() {
+		return 0.0
+	}
+
+	quorum_threshold(authorities.len()) as f64 / authorities.len() as f64 * 100.0
+}
+
+/// Derive a list of authorities from the given development seeds (e.g. `"Alice"`),
+/// using the same `//<seed>` derivation as the chain spec helpers. Returns `None`
+/// if a seed is given more than once.
+///
+/// PBFT authorities all vote with the same weight, hence no weight is attached to
+/// the derived authorities.
+#[cfg(feature = "std")]
+pub fn authorities_from_seeds(seeds: &[&str]) -> Option<AuthorityList> {
+	use sp_core::Pair;
+
+	let mut authorities = AuthorityList::with_capacity(seeds.len());
+	for seed in seeds {
+		let id = AuthorityPair::from_string(&format!("//{}", seed), None).ok()?.public();
+		if authorities.contains(&id) {
+			return None
+		}
+
+		authorities.push(id);
+	}
+
+	Some(authorities)
+}
+
+/// Tracks the latency between the import of blocks and their finalization, in
+/// milliseconds, e.g. to spot degrading finality performance. Samples are kept in
+/// a bounded histogram of fixed width buckets, latencies past the last bucket are
+/// counted in it.
+#[cfg(feature = "std")]
+#[derive(Clone, Debug)]
+pub struct LatencyTracker {
+	bucket_ms: u64,
+	buckets: Vec<u64>,
+	samples: u64,
+}
+
+#[cfg(feature = "std")]
+impl LatencyTracker {
+	/// Create a tracker with `num_buckets` buckets of `bucket_ms` milliseconds.
+	pub fn new(bucket_ms: u64, num_buckets: usize) -> Self {
+		LatencyTracker {
+			bucket_ms: bucket_ms.max(1),
+			buckets: vec![0; num_buckets.max(1)],
+			samples: 0,
+		}
+	}
+
+	/// Record the latency of a block finalized `latency_ms` after its import.
+	pub fn record(&mut self, latency_ms: u64) {
+		let index = ((latency_ms / self.bucket_ms) as usize).min(self.buckets.len() - 1);
+		self.buckets[index] += 1;
+		self.samples += 1;
+	}
+
+	/// Get the 50th, 95th and 99th latency percentiles in milliseconds. Each is
+	/// given as the upper bound of the bucket it falls in, or zero if nothing was
+	/// recorded.
+	pub fn percentiles(&self) -> (u64, u64, u64) {
+		(self.percentile(50), self.percentile(95), self.percentile(99))
+	}
+
+	fn percentile(&self, percent: u64) -> u64 {
+		if self.samples == 0 {
+			return 0
+		}
+
+		let rank = (self.samples * percent + 99) / 100;
+		let mut seen = 0;
+		for (index, count) in self.buckets.iter().enumerate() {
+			seen += count;
+			if seen >= rank {
+				return (index as u64 + 1) * self.bucket_ms
+			}
+		}
+
+		self.buckets.len() as u64 * self.bucket_ms
+	}
+}
+
+/// The versions of the `PbftApi` runtime API understood by this crate, the last
+/// one being the version declared below. A node can warn if the runtime declares
+/// a version missing from this list.
+pub const SUPPORTED_API_VERSIONS: &[u32] = &[3];
+
+/// Check whether the given `PbftApi` version is understood by this crate.
+pub fn is_api_version_supported(version: u32) -> bool {
+	SUPPORTED_API_VERSIONS.contains(&version)
 }
 
 sp_api::decl_runtime_apis! {
@@ -267,3 +1011,522 @@ sp_api::decl_runtime_apis! {
 		fn current_set_id() -> SetId;
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn authorities_from_seeds_rejects_duplicates() {
+		let authorities = authorities_from_seeds(&["Alice", "Bob", "Charlie"]).unwrap();
+		assert_eq!(authorities.len(), 3);
+		assert_eq!(authorities, authorities_from_seeds(&["Alice", "Bob", "Charlie"]).unwrap());
+
+		assert!(authorities_from_seeds(&["Alice", "Bob", "Alice"]).is_none());
+	}
+
+	#[test]
+	fn authority_id_bytes_round_trip() {
+		let authorities = authorities_from_seeds(&["Alice", "Bob"]).unwrap();
+
+		for id in authorities {
+			let bytes = authority_id_bytes(&id);
+			assert_eq!(&bytes[..], AsRef::<[u8]>::as_ref(&id));
+			assert_eq!(authority_id_from_bytes(bytes), id);
+		}
+	}
+
+	#[test]
+	fn views_since_finality_saturates() {
+		assert_eq!(views_since_finality(7, 3), 4);
+		assert_eq!(views_since_finality(3, 3), 0);
+		assert_eq!(views_since_finality(2, 3), 0);
+	}
+
+	#[test]
+	fn genesis_authorities_are_checked_against_merkle_root() {
+		let authorities = authorities_from_seeds(&["Alice", "Bob", "Charlie"]).unwrap();
+		let root = authorities_merkle_root(&authorities);
+
+		assert!(verify_genesis_authorities(&authorities, root));
+
+		let other = authorities_from_seeds(&["Alice", "Bob", "Dave"]).unwrap();
+		assert!(!verify_genesis_authorities(&other, root));
+
+		// the order of the authorities is part of the commitment.
+		let reordered = authorities_from_seeds(&["Bob", "Alice", "Charlie"]).unwrap();
+		assert!(!verify_genesis_authorities(&reordered, root));
+	}
+
+	#[test]
+	fn canonical_order_is_independent_of_input_order() {
+		let mut a = authorities_from_seeds(&["Alice", "Bob", "Charlie", "Dave"]).unwrap();
+		let mut b = authorities_from_seeds(&["Dave", "Charlie", "Alice", "Bob"]).unwrap();
+
+		canonical_order(&mut a);
+		canonical_order(&mut b);
+
+		assert_eq!(a, b);
+		assert!(a.windows(2).all(|w| authority_id_bytes(&w[0]) < authority_id_bytes(&w[1])));
+	}
+
+	#[test]
+	fn safety_margin_decreases_as_authorities_are_disabled() {
+		// 7 authorities tolerate 2 faults, the quorum is 5.
+		let levels = (0..=7).map(|disabled| safety_margin(7, disabled)).collect::<Vec<_>>();
+		assert_eq!(
+			levels,
+			vec![
+				SafetyLevel::Safe,
+				SafetyLevel::Safe,
+				SafetyLevel::Marginal,
+				SafetyLevel::Unsafe,
+				SafetyLevel::Unsafe,
+				SafetyLevel::Unsafe,
+				SafetyLevel::Unsafe,
+				SafetyLevel::Unsafe,
+			],
+		);
+
+		assert_eq!(safety_margin(1, 0), SafetyLevel::Marginal);
+		assert_eq!(safety_margin(4, 10), SafetyLevel::Unsafe);
+	}
+
+	#[test]
+	fn signature_over_payload_matches_message_signature() {
+		use sp_core::Pair;
+
+		let pair = AuthorityPair::from_string("//Alice", None).unwrap();
+		let message = leader::Message::Commit(leader::Commit {
+			target_hash: sp_core::H256::repeat_byte(1),
+			target_number: 1u64,
+		});
+
+		let payload = localized_payload(1, 0, &message);
+		let signature = pair.sign(&payload);
+
+		assert!(check_signature_over_payload(&payload, &pair.public(), &signature));
+		assert!(check_message_signature(&message, &pair.public(), &signature, 1, 0));
+
+		// the payload is localized to the view and set id.
+		assert!(!check_signature_over_payload(
+			&localized_payload(2, 0, &message),
+			&pair.public(),
+			&signature,
+		));
+	}
+
+	#[test]
+	fn equivocation_report_log_decodes() {
+		let log = equivocation_report_log::<u64>(3, 7);
+		let decoded = ConsensusLog::<u64>::decode(&mut &log.encode()[..]).unwrap();
+
+		assert_eq!(decoded, ConsensusLog::EquivocationReport(3, 7));
+	}
+
+	#[test]
+	fn finality_lag_saturates() {
+		assert_eq!(finality_lag(10u64, 7), 3);
+		assert_eq!(finality_lag(0u64, 0), 0);
+		assert_eq!(finality_lag(7u32, 10), 0);
+	}
+
+	#[test]
+	fn authority_membership_proofs_verify_against_root() {
+		for n in 1..=7 {
+			let seeds = ["Alice", "Bob", "Charlie", "Dave", "Eve", "Ferdie", "One"];
+			let authorities = authorities_from_seeds(&seeds[..n]).unwrap();
+			let root = authorities_merkle_root(&authorities);
+
+			for (index, id) in authorities.iter().enumerate() {
+				let proof = authority_merkle_proof(&authorities, index as u32).unwrap();
+				assert!(verify_authority_membership(id, &proof, root));
+
+				// the proof is bound to the authority and its position.
+				let other = &authorities[(index + 1) % n];
+				assert_eq!(verify_authority_membership(other, &proof, root), other == id);
+			}
+
+			assert!(authority_merkle_proof(&authorities, n as u32).is_none());
+		}
+
+		let outsider = authorities_from_seeds(&["Two"]).unwrap().remove(0);
+		let authorities = authorities_from_seeds(&["Alice", "Bob", "Charlie"]).unwrap();
+		let root = authorities_merkle_root(&authorities);
+		let proof = authority_merkle_proof(&authorities, 0).unwrap();
+		assert!(!verify_authority_membership(&outsider, &proof, root));
+	}
+
+	#[test]
+	fn index_remap_maps_canonical_to_onchain_positions() {
+		let mut canonical = authorities_from_seeds(&["Alice", "Bob", "Charlie", "Dave"]).unwrap();
+		canonical_order(&mut canonical);
+		let onchain = canonical.iter().rev().cloned().collect::<AuthorityList>();
+
+		let remap = index_remap(&onchain, &canonical).unwrap();
+		for (canonical_index, onchain_index) in remap.iter().enumerate() {
+			assert_eq!(canonical[canonical_index], onchain[*onchain_index as usize]);
+		}
+
+		// an `OnDisabled` index applies to the same authority in both orders.
+		let disabled = 2;
+		let position = remap.iter().position(|index| *index == disabled).unwrap();
+		assert_eq!(canonical[position], onchain[disabled as usize]);
+
+		let other = authorities_from_seeds(&["Alice", "Bob", "Charlie", "Eve"]).unwrap();
+		assert!(index_remap(&onchain, &other).is_none());
+		assert!(index_remap(&onchain, &canonical[..3].to_vec()).is_none());
+	}
+
+	#[test]
+	fn engine_tagged_signatures_are_not_interchangeable() {
+		use sp_core::Pair;
+
+		let pair = AuthorityPair::from_string("//Alice", None).unwrap();
+		let message = leader::Message::Commit(leader::Commit {
+			target_hash: sp_core::H256::repeat_byte(1),
+			target_number: 1u64,
+		});
+
+		let tagged = pair.sign(&localized_payload_with_engine(1, 0, &message));
+		let untagged = pair.sign(&localized_payload(1, 0, &message));
+
+		assert!(check_message_signature_with_engine(&message, &pair.public(), &tagged, 1, 0));
+		assert!(!check_message_signature(&message, &pair.public(), &tagged, 1, 0));
+
+		assert!(check_message_signature(&message, &pair.public(), &untagged, 1, 0));
+		assert!(!check_message_signature_with_engine(&message, &pair.public(), &untagged, 1, 0));
+	}
+
+	#[test]
+	fn catchup_candidates_exclude_known_signers() {
+		let authorities = authorities_from_seeds(&["Alice", "Bob", "Charlie", "Dave"]).unwrap();
+		let outsider = authorities_from_seeds(&["Eve"]).unwrap();
+
+		let known = vec![authorities[2].clone(), outsider[0].clone()];
+		assert_eq!(
+			catchup_candidates(&authorities, &known),
+			vec![authorities[0].clone(), authorities[1].clone(), authorities[3].clone()],
+		);
+
+		assert!(catchup_candidates(&authorities, &authorities).is_empty());
+	}
+
+	#[test]
+	fn localized_payload_len_matches_encoding() {
+		let commit = leader::Message::Commit(leader::Commit {
+			target_hash: sp_core::H256::repeat_byte(1),
+			target_number: 1u64,
+		});
+
+		for (view, set_id) in [(0, 0), (1, 2), (u64::MAX, u64::MAX)] {
+			assert_eq!(
+				localized_payload_len(view, set_id, &commit),
+				localized_payload(view, set_id, &commit).len(),
+			);
+		}
+
+		let bytes = vec![7u8; 300];
+		assert_eq!(localized_payload_len(3, 4, &bytes), localized_payload(3, 4, &bytes).len());
+	}
+
+	#[test]
+	fn view_gaps_lists_missing_interior_views() {
+		// unordered and with duplicates.
+		assert_eq!(view_gaps(&[7, 3, 4, 9, 4, 8]), vec![5, 6]);
+		assert_eq!(view_gaps(&[1, 3, 5]), vec![2, 4]);
+		assert!(view_gaps(&[2, 3, 4]).is_empty());
+		assert!(view_gaps(&[]).is_empty());
+	}
+
+	#[test]
+	fn expected_proposals_are_equal_for_equal_weights() {
+		let authorities = authorities_from_seeds(&["Alice", "Bob", "Charlie", "Dave"]).unwrap();
+		let outsider = authorities_from_seeds(&["Eve"]).unwrap();
+
+		for id in &authorities {
+			assert_eq!(expected_proposals(id, &authorities, 100), 25.0);
+		}
+
+		assert_eq!(expected_proposals(&outsider[0], &authorities, 100), 0.0);
+	}
+
+	#[test]
+	fn unknown_phase_tags_are_rejected() {
+		let target_hash = sp_core::H256::repeat_byte(1);
+		let prepare =
+			leader::Message::Prepare(leader::Prepare { target_hash, target_number: 1u64 });
+		let commit = leader::Message::Commit(leader::Commit { target_hash, target_number: 1u64 });
+
+		assert_eq!(prepare.encode()[0], PREPARE_PHASE);
+		assert_eq!(commit.encode()[0], COMMIT_PHASE);
+		assert!(is_known_phase(PRE_PREPARE_PHASE));
+
+		let mut encoded = commit.encode();
+		encoded[0] = 3;
+		assert!(!is_known_phase(encoded[0]));
+		assert!(leader::Message::<u64, sp_core::H256>::decode(&mut &encoded[..]).is_err());
+	}
+
+	#[test]
+	fn weighted_median_follows_weight() {
+		// a single heavy validator reporting an old block pulls the median down.
+		assert_eq!(weighted_median_finalized(&[(10u64, 1), (12, 1), (5, 10), (11, 1)]), Some(5));
+
+		// all the weight is at the tip.
+		assert_eq!(weighted_median_finalized(&[(10u64, 1), (20, 5), (12, 1)]), Some(20));
+
+		// equal weights give the plain (lower) median.
+		assert_eq!(weighted_median_finalized(&[(3u64, 1), (1, 1), (2, 1), (4, 1)]), Some(2));
+
+		assert_eq!(weighted_median_finalized::<u64>(&[]), None);
+		assert_eq!(weighted_median_finalized(&[(1u64, 0)]), None);
+		assert_eq!(weighted_median_finalized(&[(1u64, u64::MAX), (2, u64::MAX)]), Some(1));
+	}
+
+	#[test]
+	fn change_overlap_is_counted_in_common_members() {
+		let old = authorities_from_seeds(&["Alice", "Bob", "Charlie", "Dave"]).unwrap();
+		let new = authorities_from_seeds(&["Charlie", "Dave", "Eve", "Ferdie"]).unwrap();
+
+		// `f + 1` for 4 authorities is 2.
+		assert!(change_preserves_overlap(&old, &new, 2));
+		assert!(!change_preserves_overlap(&old, &new, 3));
+
+		let disjoint = authorities_from_seeds(&["Eve", "Ferdie"]).unwrap();
+		assert!(!change_preserves_overlap(&old, &disjoint, 1));
+		assert!(change_preserves_overlap(&old, &disjoint, 0));
+	}
+
+	#[test]
+	fn dominant_commit_target_is_the_single_quorum_target() {
+		use sp_core::Pair;
+
+		let seeds = ["Alice", "Bob", "Charlie", "Dave"];
+		let authorities = authorities_from_seeds(&seeds).unwrap();
+		let (a, b) = (sp_core::H256::repeat_byte(1), sp_core::H256::repeat_byte(2));
+
+		let commit = |seed: &str, target_hash| {
+			let pair = AuthorityPair::from_string(&format!("//{}", seed), None).unwrap();
+			let message =
+				leader::Message::Commit(leader::Commit { target_hash, target_number: 1u64 });
+			let signature = pair.sign(&localized_payload(1, 0, &message));
+			(pair.public(), signature, message)
+		};
+
+		// 3 out of 4 committed to `a`, the quorum.
+		let mut commits = vec![commit("Alice", a), commit("Bob", b), commit("Charlie", a)];
+		assert_eq!(dominant_commit_target(&commits, &authorities, 1, 0), None);
+		commits.push(commit("Dave", a));
+		assert_eq!(dominant_commit_target(&commits, &authorities, 1, 0), Some(a));
+
+		// duplicated commits and commits from outside the set aren't counted.
+		let commits = vec![
+			commit("Alice", a),
+			commit("Alice", a),
+			commit("Bob", a),
+			commit("Eve", a),
+			commit("Charlie", b),
+		];
+		assert_eq!(dominant_commit_target(&commits, &authorities, 1, 0), None);
+
+		// a signature for another view isn't counted.
+		let commits = vec![commit("Alice", a), commit("Bob", a), commit("Charlie", a)];
+		assert_eq!(dominant_commit_target(&commits, &authorities, 2, 0), None);
+	}
+
+	#[test]
+	fn latency_percentiles_over_known_sample() {
+		let mut tracker = LatencyTracker::new(10, 100);
+		assert_eq!(tracker.percentiles(), (0, 0, 0));
+
+		for i in 0..100 {
+			tracker.record(i * 10);
+		}
+		assert_eq!(tracker.percentiles(), (500, 950, 990));
+
+		// latencies past the histogram are counted in the last bucket.
+		let mut tracker = LatencyTracker::new(10, 10);
+		tracker.record(5);
+		tracker.record(5_000);
+		tracker.record(10_000);
+		assert_eq!(tracker.percentiles(), (100, 100, 100));
+	}
+
+	#[test]
+	fn memory_estimate_follows_buffered_messages() {
+		let prepare = |number| {
+			leader::Message::<u64, sp_core::H256>::Prepare(leader::Prepare {
+				target_hash: sp_core::H256::repeat_byte(1),
+				target_number: number,
+			})
+		};
+
+		let mut pool = Vec::new();
+		assert_eq!(memory_estimate(&pool), 0);
+
+		pool.push(prepare(1));
+		let one = memory_estimate(&pool);
+		assert_eq!(one, prepare(1).encoded_size());
+
+		pool.push(prepare(2));
+		pool.push(prepare(3));
+		assert_eq!(memory_estimate(&pool), 3 * one);
+
+		// pruning the two oldest messages.
+		pool.drain(..2);
+		assert_eq!(memory_estimate(&pool), one);
+	}
+
+	#[test]
+	fn split_votes_make_quorum_impossible() {
+		let authorities =
+			(1..=4).map(|byte| authority_id_from_bytes([byte; 32])).collect::<Vec<_>>();
+		let prepare = |byte| {
+			let target_hash = sp_core::H256::repeat_byte(byte);
+			leader::Message::Prepare(leader::Prepare { target_hash, target_number: 1u64 })
+		};
+		let vote = |index: usize, byte| (authorities[index].clone(), prepare(byte));
+
+		// nothing decided yet, or the leading block can still gather the missing vote.
+		assert!(!quorum_impossible::<u64, sp_core::H256>(&[], &authorities));
+		assert!(!quorum_impossible(&[vote(0, 1), vote(1, 1), vote(2, 2)], &authorities));
+
+		// with three blocks voted for, the last vote can't lift any of them to quorum.
+		let split = [vote(0, 1), vote(1, 2), vote(2, 3)];
+		assert!(quorum_impossible(&split, &authorities));
+
+		// votes repeated by an authority or cast from outside the set don't count.
+		let outsider = (authority_id_from_bytes([9; 32]), prepare(1));
+		let votes = [vote(0, 1), vote(1, 2), vote(1, 1), vote(2, 3), outsider];
+		assert!(quorum_impossible(&votes, &authorities));
+		assert!(!quorum_impossible(&[vote(0, 1), vote(0, 2), vote(1, 1)], &authorities));
+	}
+
+	#[test]
+	fn supported_api_versions_include_declared_version() {
+		use sp_api::RuntimeApiInfo;
+		use sp_runtime::testing::{Block, ExtrinsicWrapper};
+
+		let declared = <dyn PbftApi<Block<ExtrinsicWrapper<u64>>>>::VERSION;
+		assert!(is_api_version_supported(declared));
+		assert_eq!(SUPPORTED_API_VERSIONS.iter().max(), Some(&declared));
+		assert!(!is_api_version_supported(declared + 1));
+	}
+
+	#[test]
+	fn preprepare_from_primary_round_trips() {
+		use sp_application_crypto::AppKey;
+		use sp_keystore::{testing::KeyStore, SyncCryptoStore};
+		use std::sync::Arc;
+
+		let keystore: SyncCryptoStorePtr = Arc::new(KeyStore::new());
+		let authorities = ["//Alice", "//Bob"]
+			.iter()
+			.map(|seed| {
+				SyncCryptoStore::ed25519_generate_new(&*keystore, AuthorityId::ID, Some(seed))
+					.unwrap()
+					.into()
+			})
+			.collect::<AuthorityList>();
+
+		let target = (sp_core::H256::repeat_byte(1), 1u64);
+		let primary = authorities[1].clone();
+		let signed = make_preprepare(keystore.clone(), primary, 3, 0, target).unwrap();
+		assert!(matches!(&signed.message, leader::Message::PrePrepare(_)));
+		assert!(is_valid_primary(&signed, 3, 0, &authorities));
+		assert!(!is_valid_primary_with_engine(&signed, 3, 0, &authorities));
+
+		// the tagged proposal only verifies over the tagged payload.
+		let tagged =
+			make_preprepare_with_engine(keystore.clone(), authorities[1].clone(), 3, 0, target)
+				.unwrap();
+		assert!(is_valid_primary_with_engine(&tagged, 3, 0, &authorities));
+		assert!(!is_valid_primary_with_engine(&tagged, 3, 1, &authorities));
+		assert!(!is_valid_primary(&tagged, 3, 0, &authorities));
+
+		// bob isn't the primary of even views, and the signature is localized.
+		assert!(!is_valid_primary(&signed, 2, 0, &authorities));
+		assert!(!is_valid_primary(&signed, 5, 0, &authorities));
+		assert!(!is_valid_primary(&signed, 3, 1, &authorities));
+
+		// only pre-prepares are proposals.
+		let prepare = leader::Message::Prepare(leader::Prepare {
+			target_hash: target.0,
+			target_number: target.1,
+		});
+		let signed = sign_message(keystore, prepare, authorities[1].clone(), 3, 0).unwrap();
+		assert!(!is_valid_primary(&signed, 3, 0, &authorities));
+	}
+
+	#[test]
+	fn upcoming_primary_views_of_local_keys() {
+		let authorities =
+			(1..=4).map(|byte| authority_id_from_bytes([byte; 32])).collect::<Vec<_>>();
+		let local_keys = [authorities[1].clone(), authority_id_from_bytes([9; 32])];
+
+		assert_eq!(
+			upcoming_primary_views(&local_keys, &authorities, 3, 8),
+			vec![(5, authorities[1].clone()), (9, authorities[1].clone())],
+		);
+
+		// several local keys in the set.
+		let local_keys = [authorities[0].clone(), authorities[3].clone()];
+		let views = upcoming_primary_views(&local_keys, &authorities, 0, 5)
+			.into_iter()
+			.map(|(view, _)| view)
+			.collect::<Vec<_>>();
+		assert_eq!(views, vec![0, 3, 4]);
+
+		assert!(upcoming_primary_views(&local_keys, &authorities, 1, 2).is_empty());
+		assert!(upcoming_primary_views(&local_keys, &Vec::new(), 0, 5).is_empty());
+	}
+
+	#[test]
+	fn view_change_stalls_after_timeout_with_quorum() {
+		use std::time::Duration;
+
+		let timeout = Duration::from_secs(10);
+		assert!(!view_change_stalled(3, true, Duration::from_secs(9), timeout));
+		assert!(view_change_stalled(3, true, timeout, timeout));
+		assert!(view_change_stalled(3, true, Duration::from_secs(30), timeout));
+
+		// without a quorum of view changes the new primary isn't expected to act yet.
+		assert!(!view_change_stalled(3, false, Duration::from_secs(30), timeout));
+	}
+
+	#[test]
+	fn set_summary_fields_follow_inputs() {
+		let authorities =
+			(1..=4).map(|byte| authority_id_from_bytes([byte; 32])).collect::<Vec<_>>();
+
+		let summary = set_summary(2, &authorities, 100u64, Some(250));
+		assert_eq!(
+			summary,
+			SetSummary {
+				set_id: 2,
+				member_count: 4,
+				total_weight: 4,
+				activated_at: 100,
+				superseded_at: Some(250),
+			},
+		);
+
+		// the current set isn't superseded yet.
+		assert_eq!(set_summary(3, &authorities[..1].to_vec(), 250u64, None).superseded_at, None);
+	}
+
+	#[test]
+	fn quorum_percentage_of_equal_weight_sets() {
+		let authorities = |n: u8| (1..=n).map(|byte| authority_id_from_bytes([byte; 32])).collect();
+
+		// small sets don't tolerate any fault.
+		assert_eq!(quorum_percentage(&authorities(3)), 100.0);
+		assert!((quorum_percentage(&authorities(4)) - 75.0).abs() < 0.01);
+
+		// and it tends to two thirds as the set grows.
+		assert!((quorum_percentage(&authorities(100)) - 67.0).abs() < 0.01);
+		assert!((quorum_percentage(&authorities(250)) - 66.8).abs() < 0.01);
+		assert_eq!(quorum_percentage(&authorities(0)), 0.0);
+	}
+}
@@ -168,6 +168,37 @@ pub fn localized_payload_with_buffer<E: Encode>(
 	(message, round, set_id).encode_to(buf)
 }
 
+/// Encode round message localized to a given round and set id, prefixed with
+/// `TDMT_ENGINE_ID` as a domain tag.
+///
+/// The untagged payload (`localized_payload`) encodes the same way as the one of
+/// PBFT, so e.g. a PBFT commit signature could be valid for a Tendermint precommit
+/// when the same key is used for both gadgets. The engine ids differ, so a
+/// signature over a tagged payload is never valid for the other gadget's tagged
+/// payload, nor for an untagged one.
+pub fn localized_payload_with_engine<E: Encode>(round: u64, set_id: SetId, message: &E) -> Vec<u8> {
+	(TDMT_ENGINE_ID, message, round, set_id).encode()
+}
+
+/// Check a message signature by encoding the message as an engine tagged
+/// localized payload (see `localized_payload_with_engine`) and verifying the
+/// provided signature using the expected authority id.
+pub fn check_message_signature_with_engine<H, N>(
+	message: &messages::Message<H, N>,
+	id: &AuthorityId,
+	signature: &AuthoritySignature,
+	round: u64,
+	set_id: SetId,
+) -> bool
+where
+	H: Encode,
+	N: Encode,
+{
+	use sp_application_crypto::RuntimeAppPublic;
+
+	id.verify(&localized_payload_with_engine(round, set_id, message), signature)
+}
+
 /// Check a message signature by encoding the message as a localized payload and
 /// verifying the provided signature using the expected authority id.
 pub fn check_message_signature<H, N>(
@@ -214,6 +245,54 @@ where
 	valid
 }
 
+/// The vote type tag of precommits in the canonical vote sign bytes, it matches
+/// the `SignedMsgType` used for precommits by Tendermint Core.
+pub const PRECOMMIT_TYPE: u8 = 2;
+
+/// Get the canonical bytes to be signed for the given precommit, for signing
+/// compatibility with external Tendermint tooling. The bytes are the SCALE
+/// encoding of the following fields, in this order:
+///
+/// 1. `chain_id`, as a compact length-prefixed UTF-8 string;
+/// 2. the vote type, i.e. `PRECOMMIT_TYPE`;
+/// 3. the height of the target block (`target_height`);
+/// 4. the `round` the precommit was cast in;
+/// 5. the `set_id` of the authority set casting the precommit;
+/// 6. the hash of the target block (`target_hash`), `None` for a nil vote.
+///
+/// This order is part of the signing format and must never change.
+pub fn vote_sign_bytes<H, N>(
+	vote: &messages::Precommit<H, N>,
+	round: RoundNumber,
+	set_id: SetId,
+	chain_id: &str,
+) -> Vec<u8>
+where
+	H: Encode,
+	N: Encode,
+{
+	(chain_id, PRECOMMIT_TYPE, &vote.target_height, round, set_id, &vote.target_hash).encode()
+}
+
+/// Verify a signature over the canonical vote sign bytes of the given precommit,
+/// see `vote_sign_bytes`.
+pub fn verify_vote_signature<H, N>(
+	vote: &messages::Precommit<H, N>,
+	round: RoundNumber,
+	set_id: SetId,
+	chain_id: &str,
+	id: &AuthorityId,
+	signature: &AuthoritySignature,
+) -> bool
+where
+	H: Encode,
+	N: Encode,
+{
+	use sp_application_crypto::RuntimeAppPublic;
+
+	id.verify(&vote_sign_bytes(vote, round, set_id, chain_id), signature)
+}
+
 /// Localizes the message to the given set and round and signs the payload.
 #[cfg(feature = "std")]
 pub fn sign_message<H, N>(
@@ -268,3 +347,84 @@ sp_api::decl_runtime_apis! {
 		fn current_set_id() -> SetId;
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use sp_core::Pair;
+
+	#[test]
+	fn vote_signature_is_bound_to_chain_and_round() {
+		let pair = AuthorityPair::from_string("//Alice", None).unwrap();
+		let vote = messages::Precommit {
+			target_hash: Some(sp_core::H256::repeat_byte(1)),
+			target_height: 10u64,
+		};
+
+		let signature = pair.sign(&vote_sign_bytes(&vote, 1, 0, "test-chain"));
+		assert!(verify_vote_signature(&vote, 1, 0, "test-chain", &pair.public(), &signature));
+
+		assert!(!verify_vote_signature(&vote, 1, 0, "other-chain", &pair.public(), &signature));
+		assert!(!verify_vote_signature(&vote, 2, 0, "test-chain", &pair.public(), &signature));
+
+		// the chain id comes first, followed by the vote type.
+		let bytes = vote_sign_bytes(&vote, 1, 0, "test-chain");
+		assert_eq!(&bytes[..11], &"test-chain".encode()[..]);
+		assert_eq!(bytes[11], PRECOMMIT_TYPE);
+	}
+
+	#[test]
+	fn engine_tagged_signatures_do_not_cross_protocols() {
+		use finality_grandpa::leader;
+
+		// the same key used by both gadgets.
+		let pair = sp_core::ed25519::Pair::from_string("//Alice", None).unwrap();
+		let pbft_id: sp_finality_pbft::AuthorityId = pair.public().into();
+		let tdmt_id: AuthorityId = pair.public().into();
+
+		let target_hash = sp_core::H256::repeat_byte(1);
+		let commit = leader::Message::Commit(leader::Commit { target_hash, target_number: 10u64 });
+		let precommit = messages::Message::Precommit(messages::Precommit {
+			target_hash: Some(target_hash),
+			target_height: 10u64,
+		});
+
+		let pbft_payload = sp_finality_pbft::localized_payload_with_engine(1, 0, &commit);
+		let tdmt_payload = localized_payload_with_engine(1, 0, &precommit);
+		assert_ne!(pbft_payload[..4], tdmt_payload[..4]);
+
+		// a PBFT commit signature is not a valid Tendermint precommit signature.
+		let signature = pair.sign(&pbft_payload);
+		assert!(sp_finality_pbft::check_message_signature_with_engine(
+			&commit,
+			&pbft_id,
+			&signature.clone().into(),
+			1,
+			0,
+		));
+		assert!(!check_message_signature_with_engine(
+			&precommit,
+			&tdmt_id,
+			&signature.into(),
+			1,
+			0,
+		));
+
+		// and the other way round.
+		let signature = pair.sign(&tdmt_payload);
+		assert!(check_message_signature_with_engine(
+			&precommit,
+			&tdmt_id,
+			&signature.clone().into(),
+			1,
+			0,
+		));
+		assert!(!sp_finality_pbft::check_message_signature_with_engine(
+			&commit,
+			&pbft_id,
+			&signature.into(),
+			1,
+			0,
+		));
+	}
+}